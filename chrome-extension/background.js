@@ -68,7 +68,7 @@ async function checkCurrentTab() {
     const matchedCourse = matchCourse(tab.url);
     
     if (matchedCourse) {
-      handleMatch(matchedCourse, tab.url);
+      handleMatch(matchedCourse, tab.url, tab.title || '');
     } else {
       handleNoMatch();
     }
@@ -80,7 +80,8 @@ async function checkCurrentTab() {
 // URL 匹配课程
 function matchCourse(url) {
   for (const course of courses) {
-    if (matchPattern(url, course.url_pattern)) {
+    // 仅配置了桌面应用规则的课程没有 URL 规则
+    if (course.url_pattern && matchPattern(url, course.url_pattern)) {
       return course;
     }
   }
@@ -106,16 +107,16 @@ function matchPattern(url, pattern) {
 }
 
 // 匹配到课程
-async function handleMatch(course, url) {
+async function handleMatch(course, url, title) {
   const now = Math.floor(Date.now() / 1000);
   
   if (currentCourseId !== course.id || !isStudying) {
     currentCourseId = course.id;
     isStudying = true;
-    await reportStatus(course.id, true, url);
+    await reportStatus(course.id, true, url, title);
     lastReportTime = now;
   } else if (now - lastReportTime >= 5) {
-    await reportStatus(course.id, true, url);
+    await reportStatus(course.id, true, url, title);
     lastReportTime = now;
   }
 
@@ -125,7 +126,7 @@ async function handleMatch(course, url) {
 // 未匹配到课程
 async function handleNoMatch() {
   if (isStudying && currentCourseId) {
-    await reportStatus(currentCourseId, false, '');
+    await reportStatus(currentCourseId, false, '', '');
   }
   
   currentCourseId = null;
@@ -134,7 +135,7 @@ async function handleNoMatch() {
 }
 
 // 上报状态
-async function reportStatus(courseId, active, url) {
+async function reportStatus(courseId, active, url, title) {
   try {
     const response = await fetch(`${API_BASE}/status`, {
       method: 'POST',
@@ -143,7 +144,8 @@ async function reportStatus(courseId, active, url) {
         course_id: courseId,
         active,
         timestamp: Math.floor(Date.now() / 1000),
-        url
+        url,
+        title
       })
     });
     
@@ -5,6 +5,7 @@ document.addEventListener('DOMContentLoaded', () => {
   loadStatus();
   
   document.getElementById('refreshBtn').addEventListener('click', refreshCourses);
+  document.getElementById('noteBtn').addEventListener('click', saveNote);
 });
 
 async function checkConnection() {
@@ -90,6 +91,34 @@ function refreshCourses() {
   });
 }
 
+// 备注当前会话，未在学习时备注最近一次会话
+async function saveNote() {
+  const note = document.getElementById('noteInput').value.trim();
+  const focus = document.getElementById('focusSelect').value;
+  const statusEl = document.getElementById('noteStatus');
+  if (!note && !focus) return;
+
+  // 桌面应用只接受来源为 chrome-extension:// 的备注请求，
+  // 扩展有 host_permissions，请求不需要 CORS
+  try {
+    const response = await fetch(`${API_BASE}/sessions/note`, {
+      method: 'POST',
+      headers: { 'Content-Type': 'application/json' },
+      body: JSON.stringify({ note, focus: focus ? Number(focus) : null })
+    });
+    const result = await response.json();
+    if (result.success) {
+      statusEl.textContent = '备注已保存';
+      document.getElementById('noteInput').value = '';
+      document.getElementById('focusSelect').value = '';
+    } else {
+      statusEl.textContent = result.message || `保存失败（HTTP ${response.status}）`;
+    }
+  } catch (error) {
+    statusEl.textContent = '未连接到桌面应用';
+  }
+}
+
 function escapeHtml(text) {
   const div = document.createElement('div');
   div.textContent = text;
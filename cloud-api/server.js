@@ -2,10 +2,12 @@ import express from 'express'
 import cors from 'cors'
 import initSqlJs from 'sql.js'
 import { readFileSync, writeFileSync, existsSync } from 'fs'
+import { createHash, timingSafeEqual } from 'crypto'
 
 const app = express()
 app.use(cors())
-app.use(express.json())
+// 加密同步每次上传完整数据，放宽请求体大小限制
+app.use(express.json({ limit: '20mb' }))
 
 const DB_FILE = 'study_data.db'
 let db
@@ -55,9 +57,68 @@ async function initDb() {
     )
   `)
   db.run(`CREATE INDEX IF NOT EXISTS idx_logs_user_date ON study_logs(user_id, date)`)
+
+  // 多设备合并：会话和每日记录属于记录它的设备
+  db.run(`
+    CREATE TABLE IF NOT EXISTS sessions (
+      id TEXT PRIMARY KEY,
+      user_id TEXT NOT NULL,
+      course_id TEXT NOT NULL,
+      chapter_id TEXT,
+      date TEXT NOT NULL,
+      start_time INTEGER NOT NULL,
+      end_time INTEGER NOT NULL,
+      duration INTEGER NOT NULL,
+      note TEXT NOT NULL DEFAULT '',
+      focus INTEGER,
+      titles TEXT NOT NULL DEFAULT '',
+      device_id TEXT NOT NULL DEFAULT ''
+    )
+  `)
+  addColumn('study_logs', "device_id TEXT NOT NULL DEFAULT ''")
+
+  // 增量同步（第 2 版协议）：客户端的修改时间 updated_at，服务器写入顺序 seq，以及删除记录
+  for (const table of ['courses', 'study_logs', 'sessions', 'settings']) {
+    addColumn(table, 'updated_at INTEGER NOT NULL DEFAULT 0')
+    addColumn(table, 'seq INTEGER NOT NULL DEFAULT 0')
+  }
+  db.run(`
+    CREATE TABLE IF NOT EXISTS tombstones (
+      user_id TEXT NOT NULL,
+      table_name TEXT NOT NULL,
+      row_id TEXT NOT NULL,
+      deleted_at INTEGER NOT NULL,
+      seq INTEGER NOT NULL,
+      PRIMARY KEY (user_id, table_name, row_id)
+    )
+  `)
+  // 同步令牌的哈希，见 authorize
+  addColumn('users', 'token_hash TEXT')
+  // 端到端加密同步：客户端加密后的完整数据，服务器无法读取内容
+  db.run(`
+    CREATE TABLE IF NOT EXISTS blobs (
+      user_id TEXT PRIMARY KEY,
+      version INTEGER NOT NULL,
+      data TEXT NOT NULL,
+      updated_at TEXT DEFAULT CURRENT_TIMESTAMP
+    )
+  `)
+  db.run(`
+    CREATE TABLE IF NOT EXISTS meta (
+      key TEXT PRIMARY KEY,
+      value TEXT NOT NULL
+    )
+  `)
   saveDb()
 }
 
+function addColumn(table, decl) {
+  const name = decl.split(' ')[0]
+  if (!query(`PRAGMA table_info(${table})`).some(c => c.name === name)) {
+    db.run(`ALTER TABLE ${table} ADD COLUMN ${decl}`)
+  }
+}
+
 function saveDb() {
   const data = db.export()
   writeFileSync(DB_FILE, Buffer.from(data))
@@ -106,14 +167,156 @@ app.post('/api/user', (req, res) => {
   res.json({ success: true, userId })
 })
 
+const SYNC_VERSION = 2
+
+const hashToken = token => createHash('sha256').update(token).digest()
+
+// 同步令牌（Authorization: Bearer <令牌>）：用户第一次带令牌请求时登记，之后的请求都必须带相同的令牌。
+// 未登记令牌的用户仍可明文同步，但加密同步会删除明文数据，需要已登记的令牌
+// 返回 'verified'（令牌匹配）、'open'（未登记令牌）或 null（已返回 401）
+function authorize(req, res, userId) {
+  const token = (req.get('Authorization') || '').replace(/^Bearer\s+/i, '')
+  const user = get('SELECT token_hash FROM users WHERE id = ?', [userId])
+  if (user && user.token_hash) {
+    const expected = Buffer.from(user.token_hash, 'hex')
+    if (token && timingSafeEqual(hashToken(token), expected)) return 'verified'
+    res.status(401).json({ success: false, error: 'unauthorized' })
+    return null
+  }
+  if (!token) return 'open'
+  const hash = hashToken(token).toString('hex')
+  if (user) run('UPDATE users SET token_hash = ? WHERE id = ?', [hash, userId])
+  else run('INSERT INTO users (id, name, token_hash) VALUES (?, ?, ?)', [userId, 'User', hash])
+  return 'verified'
+}
+
+// 加密数据的写入和删除都要求已登记的令牌
+function requireToken(req, res, userId) {
+  const auth = authorize(req, res, userId)
+  if (auth === 'open') res.status(401).json({ success: false, error: 'token required' })
+  return auth === 'verified'
+}
+
+function currentSeq() {
+  const row = get("SELECT value FROM meta WHERE key = 'seq'")
+  return row ? parseInt(row.value) : 0
+}
+
+function nextSeq() {
+  const seq = currentSeq() + 1
+  db.run("INSERT OR REPLACE INTO meta (key, value) VALUES ('seq', ?)", [String(seq)])
+  return seq
+}
+
+// 同步的列和可在多台设备上修改的内容，与桌面端 merge.rs 一致
+const SYNC_COLUMNS = {
+  courses: ['id', 'name', 'subject', 'url_pattern', 'updated_at'],
+  study_logs: ['id', 'course_id', 'date', 'duration', 'device_id', 'updated_at'],
+  sessions: ['id', 'course_id', 'chapter_id', 'date', 'start_time', 'end_time', 'duration', 'note', 'focus', 'titles', 'device_id', 'updated_at'],
+}
+const CONTENT_FIELDS = {
+  courses: ['name', 'subject', 'url_pattern'],
+  study_logs: ['course_id', 'date', 'duration'],
+  sessions: ['note', 'focus', 'titles'],
+  settings: ['value'],
+}
+const SYNC_FIELDS = { courses: 'courses', study_logs: 'studyLogs', sessions: 'sessions', settings: 'settings' }
+
+// 删除记录按表分组，字段名与上传时相同
+const groupTombstones = (tombstones) => Object.fromEntries(Object.entries(SYNC_FIELDS).map(([table, field]) => [
+  field,
+  tombstones.filter(t => t.table_name === table).map(t => ({ id: t.row_id, deleted_at: t.deleted_at })),
+]))
+
+const contentKey = (table, row) => CONTENT_FIELDS[table].map(f => (row[f] == null ? '' : String(row[f]))).join('\x1f')
+
+// 按修改时间后写入者为准，修改时间相同时比较内容，各设备得到相同的结果
+function remoteWins(table, local, remote) {
+  return local.updated_at < remote.updated_at ||
+    (local.updated_at === remote.updated_at && contentKey(table, local) < contentKey(table, remote))
+}
+
+// 第 2 版：只包含变更，按 remoteWins 合并；返回 serverCursor 之后其它设备的变更
+// 返回的是写入本次上传之前的数据，客户端据此判断同一条记录是否在两台设备上都被修改过
+function syncV2(userId, body) {
+  const { settings, settingsUpdatedAt, deleted, serverCursor } = body
+  const keyOf = { courses: 'id', study_logs: 'id', sessions: 'id', settings: 'key' }
+  const since = serverCursor || 0
+
+  const changedSettings = query('SELECT key, value, updated_at FROM settings WHERE user_id = ? AND seq > ?', [userId, since])
+  const tombstones = query('SELECT table_name, row_id, deleted_at FROM tombstones WHERE user_id = ? AND seq > ?', [userId, since])
+  const changes = {
+    settings: Object.fromEntries(changedSettings.map(s => [s.key, s.value])),
+    settingsUpdatedAt: Object.fromEntries(changedSettings.map(s => [s.key, s.updated_at])),
+    deleted: groupTombstones(tombstones),
+  }
+  for (const [table, columns] of Object.entries(SYNC_COLUMNS)) {
+    changes[SYNC_FIELDS[table]] = query(`SELECT ${columns.join(', ')} FROM ${table} WHERE user_id = ? AND seq > ?`, [userId, since])
+  }
+
+  const wins = (table, id, remote) => {
+    const row = get(`SELECT * FROM ${table} WHERE user_id = ? AND ${keyOf[table]} = ?`, [userId, id])
+    const tomb = get('SELECT deleted_at FROM tombstones WHERE user_id = ? AND table_name = ? AND row_id = ?', [userId, table, id])
+    return row ? remoteWins(table, row, remote) : (!tomb || tomb.deleted_at < remote.updated_at)
+  }
+  const clearTombstone = (table, id) =>
+    db.run('DELETE FROM tombstones WHERE user_id = ? AND table_name = ? AND row_id = ?', [userId, table, id])
+
+  for (const [table, columns] of Object.entries(SYNC_COLUMNS)) {
+    for (const row of body[SYNC_FIELDS[table]] || []) {
+      if (table !== 'courses' && !get('SELECT id FROM courses WHERE id = ? AND user_id = ?', [row.course_id, userId])) continue
+      if (!wins(table, row.id, row)) continue
+      db.run(`INSERT OR REPLACE INTO ${table} (user_id, ${columns.join(', ')}, seq) VALUES (?, ${columns.map(() => '?').join(', ')}, ?)`,
+        [userId, ...columns.map(c => row[c] ?? null), nextSeq()])
+      clearTombstone(table, row.id)
+    }
+  }
+  for (const [key, value] of Object.entries(settings || {})) {
+    const updatedAt = (settingsUpdatedAt || {})[key] || 0
+    if (!wins('settings', key, { value: String(value), updated_at: updatedAt })) continue
+    db.run('INSERT OR REPLACE INTO settings (user_id, key, value, updated_at, seq) VALUES (?, ?, ?, ?, ?)',
+      [userId, key, String(value), updatedAt, nextSeq()])
+    clearTombstone('settings', key)
+  }
+  for (const [table, field] of Object.entries(SYNC_FIELDS)) {
+    for (const { id, deleted_at } of (deleted || {})[field] || []) {
+      const row = get(`SELECT updated_at FROM ${table} WHERE user_id = ? AND ${keyOf[table]} = ?`, [userId, id])
+      if (row && row.updated_at > deleted_at) continue
+      db.run(`DELETE FROM ${table} WHERE user_id = ? AND ${keyOf[table]} = ?`, [userId, id])
+      db.run('INSERT OR REPLACE INTO tombstones (user_id, table_name, row_id, deleted_at, seq) VALUES (?, ?, ?, ?, ?)',
+        [userId, table, id, deleted_at, nextSeq()])
+    }
+  }
+  saveDb()
+  return changes
+}
+
+// 已启用加密同步的用户只保存加密数据，不再接受或返回明文
+const hasBlob = userId => !!get('SELECT 1 AS found FROM blobs WHERE user_id = ?', [userId])
+
 app.post('/api/sync', (req, res) => {
   const { userId, courses, studyLogs, settings } = req.body
+  if (!authorize(req, res, userId)) return
+  if (hasBlob(userId)) {
+    return res.status(409).json({ success: false, error: 'encrypted' })
+  }
   
   const existingUser = get('SELECT * FROM users WHERE id = ?', [userId])
   if (!existingUser) {
     run('INSERT INTO users (id, name) VALUES (?, ?)', [userId, 'User'])
   }
   
+  if (req.body.version >= SYNC_VERSION) {
+    try {
+      const changes = syncV2(userId, req.body)
+      res.json({ success: true, synced: new Date().toISOString(), version: SYNC_VERSION, cursor: currentSeq(), changes })
+    } catch (e) {
+      console.error('Sync error:', e)
+      res.status(500).json({ success: false, error: e.message })
+    }
+    return
+  }
+
   const validCourseIds = new Set((courses || []).map(c => c.id))
 
   try {
@@ -140,6 +343,10 @@ app.post('/api/sync', (req, res) => {
 app.get('/api/data/:userId', (req, res) => {
   const { userId } = req.params
   const { startDate, endDate } = req.query
+  if (!authorize(req, res, userId)) return
+  if (hasBlob(userId)) {
+    return res.status(409).json({ success: false, error: 'encrypted' })
+  }
   
   const courses = query('SELECT * FROM courses WHERE user_id = ?', [userId])
   
@@ -150,18 +357,25 @@ app.get('/api/data/:userId', (req, res) => {
   logsQuery += ' ORDER BY date DESC'
   
   const studyLogs = query(logsQuery, params)
-  const settingsRows = query('SELECT key, value FROM settings WHERE user_id = ?', [userId])
+  const sessions = query('SELECT * FROM sessions WHERE user_id = ? ORDER BY start_time DESC', [userId])
+  const settingsRows = query('SELECT key, value, updated_at FROM settings WHERE user_id = ?', [userId])
+  const tombstones = query('SELECT table_name, row_id, deleted_at FROM tombstones WHERE user_id = ?', [userId])
   
+  // 格式与增量同步返回的 changes 相同，桌面端拉取时直接合并
   res.json({
     courses,
     studyLogs,
-    settings: Object.fromEntries(settingsRows.map(s => [s.key, s.value]))
+    sessions,
+    settings: Object.fromEntries(settingsRows.map(s => [s.key, s.value])),
+    settingsUpdatedAt: Object.fromEntries(settingsRows.map(s => [s.key, s.updated_at])),
+    deleted: groupTombstones(tombstones),
   })
 })
 
 app.get('/api/stats/:userId', (req, res) => {
   const { userId } = req.params
   const { startDate, endDate } = req.query
+  if (!authorize(req, res, userId)) return
   
   const start = startDate || '1970-01-01'
   const end = endDate || '2099-12-31'
@@ -196,6 +410,46 @@ app.get('/api/stats/:userId', (req, res) => {
   })
 })
 
+const ENCRYPTED_FORMAT = 'study-monitor-encrypted'
+
+app.get('/api/blob/:userId', (req, res) => {
+  if (!authorize(req, res, req.params.userId)) return
+  const row = get('SELECT version, data FROM blobs WHERE user_id = ?', [req.params.userId])
+  res.json({ version: row ? row.version : 0, data: row ? JSON.parse(row.data) : null })
+})
+
+// base 为客户端下载时的版本，期间其它设备已上传时返回 409，客户端重新下载合并后再上传
+app.put('/api/blob/:userId', (req, res) => {
+  const { userId } = req.params
+  const { base, data } = req.body
+  if (!requireToken(req, res, userId)) return
+  if (!data || data.format !== ENCRYPTED_FORMAT) {
+    return res.status(400).json({ success: false, error: 'invalid data' })
+  }
+  const row = get('SELECT version FROM blobs WHERE user_id = ?', [userId])
+  const version = row ? row.version : 0
+  if (base !== version) {
+    return res.status(409).json({ success: false, version })
+  }
+  // 第一次上传加密数据时删除该用户的明文数据，服务器上只保留加密后的内容
+  if (!row) {
+    for (const table of ['courses', 'study_logs', 'sessions', 'settings', 'tombstones']) {
+      db.run(`DELETE FROM ${table} WHERE user_id = ?`, [userId])
+    }
+  }
+  run('INSERT OR REPLACE INTO blobs (user_id, version, data, updated_at) VALUES (?, ?, ?, CURRENT_TIMESTAMP)',
+    [userId, version + 1, JSON.stringify(data)])
+  res.json({ success: true, synced: new Date().toISOString(), version: version + 1 })
+})
+
+// 关闭加密：删除加密数据，之后恢复明文同步
+app.delete('/api/blob/:userId', (req, res) => {
+  const { userId } = req.params
+  if (!requireToken(req, res, userId)) return
+  run('DELETE FROM blobs WHERE user_id = ?', [userId])
+  res.json({ success: true })
+})
+
 const PORT = process.env.PORT || 3000
 const HOST = '0.0.0.0'
 
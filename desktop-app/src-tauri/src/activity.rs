@@ -0,0 +1,180 @@
+// Linux 桌面应用监听：轮询 X11 焦点窗口的 WM_CLASS 和标题，匹配课程规则后上报到会话追踪
+use crate::{tracker, SharedState};
+use std::time::Duration;
+use x11rb::connection::Connection;
+use x11rb::properties::WmClass;
+use x11rb::protocol::xproto::{AtomEnum, ConnectionExt, Window};
+use x11rb::rust_connection::RustConnection;
+
+const POLL_INTERVAL: u64 = 5;
+const RECONNECT_INTERVAL: u64 = 30;
+
+x11rb::atom_manager! {
+    Atoms: AtomsCookie {
+        _NET_ACTIVE_WINDOW,
+        _NET_WM_NAME,
+        UTF8_STRING,
+    }
+}
+
+struct FocusedWindow {
+    instance: String,
+    class: String,
+    title: String,
+}
+
+struct X11Source {
+    conn: RustConnection,
+    root: Window,
+    atoms: Atoms,
+}
+
+impl X11Source {
+    fn connect() -> Option<Self> {
+        let (conn, screen_num) = x11rb::connect(None).ok()?;
+        let root = conn.setup().roots[screen_num].root;
+        let atoms = Atoms::new(&conn).ok()?.reply().ok()?;
+        Some(Self { conn, root, atoms })
+    }
+
+    fn focused_window(&self) -> Result<Option<FocusedWindow>, Box<dyn std::error::Error>> {
+        let active = self.conn
+            .get_property(false, self.root, self.atoms._NET_ACTIVE_WINDOW, AtomEnum::WINDOW, 0, 1)?
+            .reply()?;
+        let window = match active.value32().and_then(|mut v| v.next()) {
+            Some(w) if w != 0 => w,
+            _ => return Ok(None),
+        };
+
+        let (instance, class) = match WmClass::get(&self.conn, window)?.reply() {
+            Ok(Some(wm_class)) => (
+                String::from_utf8_lossy(wm_class.instance()).into_owned(),
+                String::from_utf8_lossy(wm_class.class()).into_owned(),
+            ),
+            _ => (String::new(), String::new()),
+        };
+
+        // 优先使用 _NET_WM_NAME (UTF-8)，没有时回退到 WM_NAME
+        let mut title = self.conn
+            .get_property(false, window, self.atoms._NET_WM_NAME, self.atoms.UTF8_STRING, 0, u32::MAX)?
+            .reply()?
+            .value;
+        if title.is_empty() {
+            title = self.conn
+                .get_property(false, window, AtomEnum::WM_NAME, AtomEnum::STRING, 0, u32::MAX)?
+                .reply()?
+                .value;
+        }
+
+        Ok(Some(FocusedWindow {
+            instance,
+            class,
+            title: String::from_utf8_lossy(&title).into_owned(),
+        }))
+    }
+}
+
+fn tracking_enabled(state: &SharedState) -> bool {
+    state.lock().db.get_setting("app_tracking_enabled")
+        .map(|v| v == "true")
+        .unwrap_or(true)
+}
+
+pub fn start_watcher(state: SharedState) {
+    // 当前由本监听器开启的课程，离开焦点时只暂停自己开启的会话，不影响浏览器上报
+    let mut active_course: Option<String> = None;
+
+    loop {
+        let source = match X11Source::connect() {
+            Some(source) => source,
+            None => {
+                std::thread::sleep(Duration::from_secs(RECONNECT_INTERVAL));
+                continue;
+            }
+        };
+        println!("Activity watcher connected to X11");
+
+        loop {
+            std::thread::sleep(Duration::from_secs(POLL_INTERVAL));
+            let now = chrono::Utc::now().timestamp();
+
+            let window = if tracking_enabled(&state) {
+                match source.focused_window() {
+                    Ok(window) => window,
+                    Err(e) => {
+                        println!("Activity watcher lost X11 connection: {}", e);
+                        break;
+                    }
+                }
+            } else {
+                None
+            };
+
+            let matched = window.and_then(|w| {
+                let courses = state.lock().db.get_courses();
+                tracker::match_window(&courses, &[&w.instance, &w.class], &w.title)
+                    .map(|c| (c.id.clone(), w.title))
+            });
+
+            match matched {
+                Some((course_id, title)) => {
+                    tracker::report_status(&state, &course_id, true, now, &title, &title);
+                    active_course = Some(course_id);
+                }
+                None => {
+                    if let Some(course_id) = active_course.take() {
+                        tracker::report_status(&state, &course_id, false, now, "", "");
+                    }
+                }
+            }
+        }
+
+        if let Some(course_id) = active_course.take() {
+            tracker::report_status(&state, &course_id, false, chrono::Utc::now().timestamp(), "", "");
+        }
+        std::thread::sleep(Duration::from_secs(RECONNECT_INTERVAL));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use x11rb::protocol::xproto::{CreateWindowAux, PropMode, WindowClass};
+    use x11rb::wrapper::ConnectionExt as _;
+
+    // 需要 X 服务器：DISPLAY=:99 Xvfb :99 & cargo test -- --ignored
+    // Xvfb 没有窗口管理器，测试自己设置根窗口的 _NET_ACTIVE_WINDOW
+    #[test]
+    #[ignore]
+    fn reads_focused_window_from_x11() {
+        let source = X11Source::connect().expect("需要设置 DISPLAY 并运行 Xvfb");
+        let conn = &source.conn;
+        let window = conn.generate_id().unwrap();
+        conn.create_window(
+            x11rb::COPY_DEPTH_FROM_PARENT,
+            window,
+            source.root,
+            0,
+            0,
+            100,
+            100,
+            0,
+            WindowClass::INPUT_OUTPUT,
+            0,
+            &CreateWindowAux::new(),
+        )
+        .unwrap();
+        conn.change_property8(PropMode::REPLACE, window, AtomEnum::WM_CLASS, AtomEnum::STRING, b"anki\0Anki\0")
+            .unwrap();
+        conn.change_property8(PropMode::REPLACE, window, source.atoms._NET_WM_NAME, source.atoms.UTF8_STRING, "用户 1 - Anki".as_bytes())
+            .unwrap();
+        conn.change_property32(PropMode::REPLACE, source.root, source.atoms._NET_ACTIVE_WINDOW, AtomEnum::WINDOW, &[window])
+            .unwrap();
+        conn.flush().unwrap();
+
+        let focused = source.focused_window().unwrap().expect("应有焦点窗口");
+        assert_eq!(focused.instance, "anki");
+        assert_eq!(focused.class, "Anki");
+        assert_eq!(focused.title, "用户 1 - Anki");
+    }
+}
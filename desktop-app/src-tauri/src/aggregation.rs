@@ -0,0 +1,103 @@
+use crate::{PeriodStat, Subject, SubjectDuration, SubjectStat};
+use chrono::{Datelike, Duration, NaiveDate};
+use std::collections::{BTreeMap, HashMap};
+
+pub fn is_valid_granularity(granularity: &str) -> bool {
+    matches!(granularity, "day" | "week" | "month" | "year")
+}
+
+// 日期所在的统计区间：(标签, 开始日期, 结束日期)，周按 ISO 周计算
+fn bucket_of(granularity: &str, date: NaiveDate) -> (String, NaiveDate, NaiveDate) {
+    match granularity {
+        "week" => {
+            let week = date.iso_week();
+            let start = NaiveDate::from_isoywd_opt(week.year(), week.week(), chrono::Weekday::Mon).unwrap();
+            (format!("{}-W{:02}", week.year(), week.week()), start, start + Duration::days(6))
+        }
+        "month" => {
+            let (start, end) = crate::goals::period_range("monthly", date);
+            (date.format("%Y-%m").to_string(), start, end)
+        }
+        "year" => (
+            date.year().to_string(),
+            NaiveDate::from_ymd_opt(date.year(), 1, 1).unwrap(),
+            NaiveDate::from_ymd_opt(date.year(), 12, 31).unwrap(),
+        ),
+        _ => (date.format("%Y-%m-%d").to_string(), date, date),
+    }
+}
+
+// rows 为 (日期, 科目, 时长)，按区间汇总并补齐 start..=end 中没有学习的区间
+pub fn build_buckets(rows: &[(String, String, i64)], granularity: &str, start: NaiveDate, end: NaiveDate) -> Vec<PeriodStat> {
+    let mut buckets: BTreeMap<NaiveDate, PeriodStat> = BTreeMap::new();
+    let mut breakdown: BTreeMap<NaiveDate, BTreeMap<String, i64>> = BTreeMap::new();
+
+    let mut date = start;
+    while date <= end {
+        let (label, bucket_start, bucket_end) = bucket_of(granularity, date);
+        buckets.entry(bucket_start).or_insert_with(|| PeriodStat {
+            period: label,
+            start_date: bucket_start.format("%Y-%m-%d").to_string(),
+            end_date: bucket_end.format("%Y-%m-%d").to_string(),
+            duration: 0,
+            subjects: Vec::new(),
+        });
+        date = bucket_end + Duration::days(1);
+    }
+
+    for (date, subject, duration) in rows {
+        let Ok(date) = NaiveDate::parse_from_str(date, "%Y-%m-%d") else {
+            continue;
+        };
+        let (_, bucket_start, _) = bucket_of(granularity, date);
+        if let Some(bucket) = buckets.get_mut(&bucket_start) {
+            bucket.duration += duration;
+            *breakdown.entry(bucket_start).or_default().entry(subject.clone()).or_default() += duration;
+        }
+    }
+
+    for (bucket_start, subjects) in breakdown {
+        if let Some(bucket) = buckets.get_mut(&bucket_start) {
+            bucket.subjects = subjects
+                .into_iter()
+                .map(|(subject, duration)| SubjectDuration { subject, duration })
+                .collect();
+        }
+    }
+
+    buckets.into_values().collect()
+}
+
+// 科目汇总：duration 为直属课程的时长，total_duration 额外累加所有子科目
+pub fn roll_up_subjects(subjects: &[Subject], own: &HashMap<String, i64>) -> Vec<SubjectStat> {
+    let parent_of: HashMap<&str, &str> = subjects
+        .iter()
+        .filter_map(|s| s.parent_id.as_deref().map(|p| (s.id.as_str(), p)))
+        .collect();
+    let mut totals: HashMap<&str, i64> = HashMap::new();
+    for subject in subjects {
+        let duration = own.get(&subject.id).copied().unwrap_or(0);
+        // 沿父级链向上累加，层数限制防止数据异常时出现环
+        let mut id = subject.id.as_str();
+        for _ in 0..=subjects.len() {
+            *totals.entry(id).or_default() += duration;
+            match parent_of.get(id) {
+                Some(parent) => id = parent,
+                None => break,
+            }
+        }
+    }
+
+    subjects
+        .iter()
+        .map(|s| SubjectStat {
+            id: s.id.clone(),
+            name: s.name.clone(),
+            color: s.color.clone(),
+            parent_id: s.parent_id.clone(),
+            sort_order: s.sort_order,
+            duration: own.get(&s.id).copied().unwrap_or(0),
+            total_duration: totals.get(s.id.as_str()).copied().unwrap_or(0),
+        })
+        .collect()
+}
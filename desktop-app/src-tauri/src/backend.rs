@@ -0,0 +1,699 @@
+use crate::crypto;
+use crate::db::Database;
+use crate::merge;
+use crate::sync::{self, Keys, SYNCED_SETTINGS};
+use crate::SharedState;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::io::Read;
+use std::path::PathBuf;
+use std::time::Duration;
+
+// 可选的同步方式：自建的 cloud-api 服务器、WebDAV 服务器、本地或共享文件夹（Syncthing、Nextcloud 等同步的目录）
+pub const BACKENDS: [&str; 3] = ["http", "webdav", "folder"];
+
+const REQUEST_TIMEOUT_SECS: u64 = 30;
+// 增量同步协议版本，服务器响应中没有版本号时退回完整上传
+const PROTOCOL_VERSION: i64 = 2;
+// 加密同步上传时其它设备恰好也在上传，重新下载合并的次数上限
+const UPLOAD_ATTEMPTS: u32 = 3;
+// 本机未启用加密，但其它设备已上传加密数据
+const ENCRYPTED_ELSEWHERE: &str = "其它设备已启用加密同步，请先输入同步密码";
+
+// 文件同步：每台设备只写自己的文件 device-<设备 id>.json，读取其它设备的文件后合并，
+// 不会有两台设备同时写同一个文件
+const FILE_PREFIX: &str = "device-";
+const FILE_SUFFIX: &str = ".json";
+const FILE_FORMAT: &str = "study-monitor-changes";
+const FILE_VERSION: i64 = 1;
+
+pub trait SyncBackend {
+    // 与远端交换一次数据：写入其它设备的变更，上传本机的数据，返回同步时间
+    fn exchange(&self, state: &SharedState) -> Result<String, String>;
+    // 远端的完整数据（已解密），格式与增量同步的变更相同，用于拉取预览
+    fn fetch(&self, keys: Option<&Keys>) -> Result<Value, String>;
+    // 远端已有的加密数据密钥（用同步密码加密），还没有加密数据时为 None
+    fn wrapped_key(&self) -> Result<Option<Value>, String>;
+    // 关闭加密时删除远端的加密数据；文件同步下次交换时会改写为明文，无需处理
+    fn remove_encrypted(&self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+// 按设置选择同步方式，未配置完整时返回错误
+pub fn from_settings(db: &Database) -> Result<Box<dyn SyncBackend>, String> {
+    let setting = |key: &str| db.get_setting(key).filter(|v| !v.is_empty());
+    match setting("sync_backend").as_deref().unwrap_or("http") {
+        "webdav" => Ok(Box::new(FileBackend {
+            store: WebDav {
+                url: setting("webdav_url").ok_or("请先配置 WebDAV 地址")?,
+                username: setting("webdav_username").unwrap_or_default(),
+                password: setting("webdav_password").unwrap_or_default(),
+            },
+            own: file_name(&db.device_id()),
+        })),
+        "folder" => Ok(Box::new(FileBackend {
+            store: Folder { dir: PathBuf::from(setting("sync_folder").ok_or("请先选择同步文件夹")?) },
+            own: file_name(&db.device_id()),
+        })),
+        _ => match (setting("sync_url"), setting("user_id")) {
+            (Some(url), Some(user_id)) => Ok(Box::new(HttpBackend {
+                url,
+                user_id,
+                token: setting("sync_token").unwrap_or_default(),
+            })),
+            _ => Err("请先配置同步服务器和用户 ID".to_string()),
+        },
+    }
+}
+
+fn agent() -> ureq::Agent {
+    ureq::AgentBuilder::new()
+        .timeout(Duration::from_secs(REQUEST_TIMEOUT_SECS))
+        .build()
+}
+
+fn now() -> String {
+    chrono::Utc::now().to_rfc3339()
+}
+
+// cloud-api 服务器：未加密时使用增量同步协议，由服务器合并；加密时服务器只保存加密后的完整数据
+struct HttpBackend {
+    url: String,
+    user_id: String,
+    // 同步令牌，第一次带令牌同步时由服务器登记；加密同步必须设置
+    token: String,
+}
+
+fn read_response(response: Result<ureq::Response, ureq::Error>) -> Result<Value, String> {
+    let response = response.map_err(|e| match e {
+        ureq::Error::Status(401, _) => "同步令牌错误，加密同步需要先设置同步令牌".to_string(),
+        ureq::Error::Status(code, _) => format!("服务器返回 HTTP {}", code),
+        ureq::Error::Transport(t) => format!("无法连接服务器：{}", t),
+    })?;
+    response.into_json().map_err(|e| format!("无效的服务器响应：{}", e))
+}
+
+// 服务器上已有加密数据时拒绝明文同步
+fn read_plain_response(response: Result<ureq::Response, ureq::Error>) -> Result<Value, String> {
+    match response {
+        Err(ureq::Error::Status(409, _)) => Err(ENCRYPTED_ELSEWHERE.to_string()),
+        other => read_response(other),
+    }
+}
+
+fn synced_at(response: &Value) -> String {
+    response["synced"].as_str().unwrap_or_default().to_string()
+}
+
+impl HttpBackend {
+    fn endpoint(&self, path: &str) -> String {
+        format!("{}/api/{}/{}", self.url.trim_end_matches('/'), path, self.user_id)
+    }
+
+    fn request(&self, method: &str, url: &str) -> ureq::Request {
+        let request = agent().request(method, url);
+        match self.token.as_str() {
+            "" => request,
+            token => request.set("Authorization", &format!("Bearer {}", token)),
+        }
+    }
+
+    fn post(&self, body: &Value) -> Result<Value, String> {
+        read_plain_response(self.request("POST", &format!("{}/api/sync", self.url.trim_end_matches('/'))).send_json(body))
+    }
+
+    // 服务器上的加密数据及其版本，还没有数据时为 None
+    fn get_blob(&self) -> Result<(i64, Option<Value>), String> {
+        let response = match self.request("GET", &self.endpoint("blob")).call() {
+            Err(ureq::Error::Status(404, _)) => return Err("服务器不支持加密同步，请更新 cloud-api".to_string()),
+            other => read_response(other)?,
+        };
+        let data = Some(response["data"].clone()).filter(|d| !d.is_null());
+        Ok((response["version"].as_i64().unwrap_or(0), data))
+    }
+
+    // 上传加密数据，base 为下载时的版本；其它设备在此期间已上传时返回 None
+    fn put_blob(&self, base: i64, data: &Value) -> Result<Option<String>, String> {
+        let body = json!({ "base": base, "data": data });
+        match self.request("PUT", &self.endpoint("blob")).send_json(body) {
+            Err(ureq::Error::Status(409, _)) => Ok(None),
+            other => read_response(other).map(|r| Some(synced_at(&r))),
+        }
+    }
+
+    // 增量同步：上传上次同步以来的本地变更，写入服务器返回的其它设备的变更
+    fn exchange_plain(&self, state: &SharedState) -> Result<String, String> {
+        let (body, snapshot) = {
+            let s = state.lock();
+            // 变更在持有状态锁时读取，此后的修改时间都不早于 snapshot
+            let snapshot = chrono::Utc::now().timestamp_millis();
+            let since = sync::cursor(&s.db, "sync_cursor");
+            let mut body = s.db.get_sync_changes(since, &SYNCED_SETTINGS);
+            body["userId"] = self.user_id.as_str().into();
+            body["version"] = PROTOCOL_VERSION.into();
+            body["since"] = since.into();
+            body["serverCursor"] = sync::cursor(&s.db, "sync_server_cursor").into();
+            (body, snapshot)
+        };
+        let response = self.post(&body)?;
+
+        if response["version"].as_i64().unwrap_or(0) < PROTOCOL_VERSION {
+            // 旧版服务器只接受完整数据，不保存游标，下次仍从头上传
+            let full = {
+                let s = state.lock();
+                let mut full = sync::payload(&s.db);
+                full["userId"] = self.user_id.as_str().into();
+                full
+            };
+            return self.post(&full).map(|r| synced_at(&r));
+        }
+
+        let s = state.lock();
+        let applied = sync::changed(&s.db.apply_sync_changes(&response["changes"], &SYNCED_SETTINGS, sync::conflict_since(&s.db))?);
+        if applied > 0 {
+            println!("Applied {} remote changes", applied);
+        }
+        s.db.set_setting("sync_cursor", &snapshot.to_string());
+        if let Some(server_cursor) = response["cursor"].as_i64() {
+            s.db.set_setting("sync_server_cursor", &server_cursor.to_string());
+        }
+        Ok(synced_at(&response))
+    }
+
+    // 加密同步：先下载合并，再上传合并后的完整数据
+    fn exchange_encrypted(&self, state: &SharedState) -> Result<String, String> {
+        for _ in 0..UPLOAD_ATTEMPTS {
+            let (version, remote) = self.get_blob()?;
+            let (blob, snapshot) = {
+                let s = state.lock();
+                let mut keys = sync::keys(&s.db).ok_or("未启用加密同步")?;
+                let snapshot = chrono::Utc::now().timestamp_millis();
+                if let Some(envelope) = &remote {
+                    let data = sync::decrypt(&keys, &self.user_id, envelope)?;
+                    let applied = sync::changed(&s.db.apply_sync_changes(&data, &SYNCED_SETTINGS, sync::conflict_since(&s.db))?);
+                    if applied > 0 {
+                        println!("Applied {} remote changes", applied);
+                    }
+                    sync::adopt_wrapped_key(&s.db, &mut keys, &envelope["wrapped_key"]);
+                }
+                let data = s.db.get_sync_changes(0, &SYNCED_SETTINGS);
+                (crypto::encrypt(&keys.key, &keys.wrapped, &self.user_id, &data), snapshot)
+            };
+
+            if let Some(synced) = self.put_blob(version, &blob)? {
+                let s = state.lock();
+                s.db.set_setting("sync_cursor", &snapshot.to_string());
+                // 服务器上已是新密钥加密的数据，不再需要旧密钥
+                s.db.set_setting("sync_previous_key", "");
+                return Ok(synced);
+            }
+        }
+        Err("其它设备正在同步，请稍后重试".to_string())
+    }
+}
+
+impl SyncBackend for HttpBackend {
+    fn exchange(&self, state: &SharedState) -> Result<String, String> {
+        let encrypted = sync::keys(&state.lock().db).is_some();
+        if encrypted {
+            self.exchange_encrypted(state)
+        } else {
+            self.exchange_plain(state)
+        }
+    }
+
+    fn fetch(&self, keys: Option<&Keys>) -> Result<Value, String> {
+        match keys {
+            Some(keys) => {
+                let envelope = self.get_blob()?.1.ok_or("服务器上还没有加密数据")?;
+                sync::decrypt(keys, &self.user_id, &envelope)
+            }
+            None => read_plain_response(self.request("GET", &self.endpoint("data")).call()),
+        }
+    }
+
+    fn wrapped_key(&self) -> Result<Option<Value>, String> {
+        Ok(self.get_blob()?.1.map(|envelope| envelope["wrapped_key"].clone()))
+    }
+
+    // 旧版服务器没有加密数据，返回 404 时视为已删除
+    fn remove_encrypted(&self) -> Result<(), String> {
+        match self.request("DELETE", &self.endpoint("blob")).call() {
+            Err(ureq::Error::Status(404, _)) => Ok(()),
+            other => read_response(other).map(|_| ()),
+        }
+    }
+}
+
+// 以文件交换数据的存储，文件名只列出 device-*.json
+trait FileStore {
+    fn list(&self) -> Result<Vec<String>, String>;
+    // 文件不存在时返回 None
+    fn read(&self, name: &str) -> Result<Option<Vec<u8>>, String>;
+    fn write(&self, name: &str, data: &[u8]) -> Result<(), String>;
+}
+
+fn file_name(device_id: &str) -> String {
+    format!("{}{}{}", FILE_PREFIX, device_id, FILE_SUFFIX)
+}
+
+fn is_device_file(name: &str) -> bool {
+    name.starts_with(FILE_PREFIX) && name.ends_with(FILE_SUFFIX)
+}
+
+// 本地或共享文件夹，完全离线可用
+struct Folder {
+    dir: PathBuf,
+}
+
+impl FileStore for Folder {
+    fn list(&self) -> Result<Vec<String>, String> {
+        let entries = std::fs::read_dir(&self.dir).map_err(|e| format!("无法读取同步文件夹 {}：{}", self.dir.display(), e))?;
+        Ok(entries
+            .filter_map(|e| e.ok())
+            .filter_map(|e| e.file_name().into_string().ok())
+            .filter(|name| is_device_file(name))
+            .collect())
+    }
+
+    fn read(&self, name: &str) -> Result<Option<Vec<u8>>, String> {
+        match std::fs::read(self.dir.join(name)) {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(format!("读取 {} 失败：{}", name, e)),
+        }
+    }
+
+    // 先写临时文件再改名，其它设备和同步工具不会读到写了一半的文件
+    fn write(&self, name: &str, data: &[u8]) -> Result<(), String> {
+        let temp = self.dir.join(format!(".{}.tmp", name));
+        std::fs::write(&temp, data).map_err(|e| format!("写入同步文件夹失败：{}", e))?;
+        std::fs::rename(&temp, self.dir.join(name)).map_err(|e| format!("写入同步文件夹失败：{}", e))
+    }
+}
+
+// WebDAV 服务器（Nextcloud、坚果云等），url 为存放同步文件的目录
+struct WebDav {
+    url: String,
+    username: String,
+    password: String,
+}
+
+const PROPFIND_BODY: &str = r#"<?xml version="1.0" encoding="utf-8"?><d:propfind xmlns:d="DAV:"><d:prop><d:resourcetype/></d:prop></d:propfind>"#;
+
+fn dav_error(e: ureq::Error) -> String {
+    match e {
+        ureq::Error::Status(401, _) | ureq::Error::Status(403, _) => "WebDAV 用户名或密码错误".to_string(),
+        ureq::Error::Status(code, _) => format!("WebDAV 服务器返回 HTTP {}", code),
+        ureq::Error::Transport(t) => format!("无法连接 WebDAV 服务器：{}", t),
+    }
+}
+
+// PROPFIND 响应中的 href，命名空间前缀因服务器而异（d:href、D:href 或 href）
+fn hrefs(xml: &str) -> Vec<String> {
+    let mut result = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find("href>") {
+        let closing = rest[..start].rsplit('<').next().unwrap_or_default().starts_with('/');
+        rest = &rest[start + "href>".len()..];
+        if let (false, Some(end)) = (closing, rest.find('<')) {
+            result.push(rest[..end].trim().to_string());
+        }
+    }
+    result
+}
+
+impl WebDav {
+    fn request(&self, method: &str, name: &str) -> ureq::Request {
+        let request = agent().request(method, &format!("{}/{}", self.url.trim_end_matches('/'), name));
+        if self.username.is_empty() {
+            return request;
+        }
+        let credentials = STANDARD.encode(format!("{}:{}", self.username, self.password));
+        request.set("Authorization", &format!("Basic {}", credentials))
+    }
+}
+
+impl FileStore for WebDav {
+    fn list(&self) -> Result<Vec<String>, String> {
+        let response = match self
+            .request("PROPFIND", "")
+            .set("Depth", "1")
+            .set("Content-Type", "application/xml; charset=utf-8")
+            .send_string(PROPFIND_BODY)
+        {
+            // 目录还不存在，第一次上传时创建
+            Err(ureq::Error::Status(404, _)) => return Ok(Vec::new()),
+            other => other.map_err(dav_error)?,
+        };
+        let xml = response.into_string().map_err(|e| format!("无效的 WebDAV 响应：{}", e))?;
+        Ok(hrefs(&xml)
+            .iter()
+            .filter_map(|href| href.trim_end_matches('/').rsplit('/').next().map(|n| n.to_string()))
+            .filter(|name| is_device_file(name))
+            .collect())
+    }
+
+    fn read(&self, name: &str) -> Result<Option<Vec<u8>>, String> {
+        let response = match self.request("GET", name).call() {
+            Err(ureq::Error::Status(404, _)) => return Ok(None),
+            other => other.map_err(dav_error)?,
+        };
+        let mut data = Vec::new();
+        response
+            .into_reader()
+            .read_to_end(&mut data)
+            .map_err(|e| format!("读取 {} 失败：{}", name, e))?;
+        Ok(Some(data))
+    }
+
+    fn write(&self, name: &str, data: &[u8]) -> Result<(), String> {
+        match self.request("PUT", name).send_bytes(data) {
+            // 目录不存在时先创建
+            Err(ureq::Error::Status(404, _)) | Err(ureq::Error::Status(409, _)) => {
+                match self.request("MKCOL", "").call() {
+                    Ok(_) | Err(ureq::Error::Status(405, _)) => {}
+                    Err(e) => return Err(dav_error(e)),
+                }
+                self.request("PUT", name).send_bytes(data).map_err(dav_error)?;
+            }
+            other => {
+                other.map_err(dav_error)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// 文件同步的各方式共用的交换逻辑，own 为本机的文件名
+struct FileBackend<S> {
+    store: S,
+    own: String,
+}
+
+fn digest(text: &str) -> String {
+    Sha256::digest(text.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// 其它设备文件中的数据；加密数据用文件名作为附加数据，不能被换到其它文件下使用
+fn open_file(keys: Option<&Keys>, name: &str, file: &Value) -> Result<Option<Value>, String> {
+    if file["format"] != FILE_FORMAT || file["version"].as_i64().unwrap_or(i64::MAX) > FILE_VERSION {
+        return Err(format!("无法识别的同步文件 {}，请升级应用", name));
+    }
+    let data = &file["data"];
+    if data["format"] != crypto::FORMAT {
+        return Ok(Some(data.clone()));
+    }
+    let keys = keys.ok_or(ENCRYPTED_ELSEWHERE)?;
+    if !keys.knows(&data["key_id"]) {
+        // 其它设备更换了数据密钥时需要输入新密码；仍在使用更换前密钥的设备同步后会改用新密钥，先跳过
+        if data["wrapped_key"]["created_at"].as_i64() > keys.wrapped["created_at"].as_i64() {
+            return Err("同步密钥已在其它设备上更换，请重新输入同步密码".to_string());
+        }
+        return Ok(None);
+    }
+    sync::decrypt(keys, name, data).map(Some)
+}
+
+impl<S: FileStore> FileBackend<S> {
+
+    // 其它设备的文件；seen 中记录的导出时间没有变化的文件跳过
+    fn read_files(&self, names: &[String], seen: &serde_json::Map<String, Value>) -> Result<Vec<(String, Value)>, String> {
+        let mut files = Vec::new();
+        for name in names.iter().filter(|n| **n != self.own) {
+            let name = name.clone();
+            let Some(bytes) = self.store.read(&name)? else {
+                continue;
+            };
+            let file: Value = serde_json::from_slice(&bytes).map_err(|e| format!("无效的同步文件 {}：{}", name, e))?;
+            if seen.get(&name).and_then(|v| v.as_i64()).is_some_and(|at| Some(at) == file["exported_at"].as_i64()) {
+                continue;
+            }
+            files.push((name, file));
+        }
+        Ok(files)
+    }
+}
+
+impl<S: FileStore> SyncBackend for FileBackend<S> {
+    fn exchange(&self, state: &SharedState) -> Result<String, String> {
+        let listed = self.store.list()?;
+        let mut seen = {
+            let s = state.lock();
+            s.db.get_setting("sync_seen_files")
+                .and_then(|v| serde_json::from_str::<Value>(&v).ok())
+                .and_then(|v| v.as_object().cloned())
+                .unwrap_or_default()
+        };
+        let files = self.read_files(&listed, &seen)?;
+        // 其它设备的文件中包含它们读到的本机数据；本机上次导出的版本不算远端修改，
+        // 否则本机之后的修改会与自己的旧版本冲突。冲突规则本身与服务器同步相同
+        let own_file = if files.is_empty() {
+            None
+        } else {
+            self.store.read(&self.own)?.and_then(|bytes| serde_json::from_slice::<Value>(&bytes).ok())
+        };
+
+        let (content, hash, snapshot) = {
+            let s = state.lock();
+            let mut keys = sync::keys(&s.db);
+            // 本机文件无法读取时（如密钥已更换）不过滤
+            let own = own_file
+                .and_then(|file| open_file(keys.as_ref(), &self.own, &file).ok().flatten())
+                .unwrap_or(Value::Null);
+            let mut changes = Vec::new();
+            for (name, file) in &files {
+                if let Some(keys) = keys.as_mut() {
+                    sync::adopt_wrapped_key(&s.db, keys, &file["data"]["wrapped_key"]);
+                }
+                if let Some(data) = open_file(keys.as_ref(), name, file)? {
+                    changes.push(merge::without_known(&data, &own));
+                }
+                seen.insert(name.clone(), file["exported_at"].clone());
+            }
+            if !changes.is_empty() {
+                let applied = sync::changed(&s.db.apply_sync_changes(&merge::combine(&changes), &SYNCED_SETTINGS, sync::conflict_since(&s.db))?);
+                if applied > 0 {
+                    println!("Applied {} remote changes", applied);
+                }
+            }
+
+            let snapshot = chrono::Utc::now().timestamp_millis();
+            let data = s.db.get_sync_changes(0, &SYNCED_SETTINGS);
+            // 数据和密钥都没有变化时不重写本机文件，其它设备也不必重新读取
+            let wrapped = keys.as_ref().map(|k| k.wrapped.to_string()).unwrap_or_default();
+            let hash = digest(&format!("{}{}", data, wrapped));
+            let unchanged = listed.contains(&self.own) && s.db.get_setting("sync_file_hash").as_deref() == Some(hash.as_str());
+            let data = match &keys {
+                Some(keys) => crypto::encrypt(&keys.key, &keys.wrapped, &self.own, &data),
+                None => data,
+            };
+            let content = (!unchanged).then(|| {
+                json!({
+                    "format": FILE_FORMAT,
+                    "version": FILE_VERSION,
+                    "device_id": s.db.device_id(),
+                    "exported_at": snapshot,
+                    "data": data,
+                })
+            });
+            (content, hash, snapshot)
+        };
+
+        if let Some(content) = content {
+            self.store.write(&self.own, content.to_string().as_bytes())?;
+        }
+        let s = state.lock();
+        s.db.set_setting("sync_cursor", &snapshot.to_string());
+        s.db.set_setting("sync_file_hash", &hash);
+        s.db.set_setting("sync_seen_files", &Value::Object(seen).to_string());
+        Ok(now())
+    }
+
+    fn fetch(&self, keys: Option<&Keys>) -> Result<Value, String> {
+        let mut changes = Vec::new();
+        for (name, file) in self.read_files(&self.store.list()?, &serde_json::Map::new())? {
+            changes.extend(open_file(keys, &name, &file)?);
+        }
+        Ok(merge::combine(&changes))
+    }
+
+    // 各设备文件中最新设置的同步密码
+    fn wrapped_key(&self) -> Result<Option<Value>, String> {
+        Ok(self
+            .read_files(&self.store.list()?, &serde_json::Map::new())?
+            .into_iter()
+            .map(|(_, file)| file["data"]["wrapped_key"].clone())
+            .filter(|wrapped| wrapped.is_object())
+            .max_by_key(|wrapped| wrapped["created_at"].as_i64()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AppState;
+    use parking_lot::Mutex;
+    use std::path::Path;
+    use std::sync::Arc;
+
+    // 测试用的同步文件夹，结束时删除
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            let dir = std::env::temp_dir().join(format!("study-monitor-sync-{}", uuid::Uuid::new_v4()));
+            std::fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    // 一台设备：内存数据库和指向共享文件夹的文件同步
+    fn device(dir: &Path) -> (SharedState, FileBackend<Folder>) {
+        let db = Database::in_memory().unwrap();
+        let own = file_name(&db.device_id());
+        let backend = FileBackend { store: Folder { dir: dir.to_path_buf() }, own };
+        (Arc::new(Mutex::new(AppState::new(db))), backend)
+    }
+
+    fn add_course(state: &SharedState, name: &str) -> String {
+        let s = state.lock();
+        if s.db.get_subjects().is_empty() {
+            s.db.add_subject("数学", "", None).unwrap();
+        }
+        let subject_id = s.db.get_subjects()[0].id.clone();
+        s.db.add_course(name, &subject_id, "", "", "").unwrap()
+    }
+
+    fn exported_at(backend: &FileBackend<Folder>) -> i64 {
+        let file: Value = serde_json::from_slice(&backend.store.read(&backend.own).unwrap().unwrap()).unwrap();
+        file["exported_at"].as_i64().unwrap()
+    }
+
+    #[test]
+    fn exchanges_changes_between_devices() {
+        let dir = TempDir::new();
+        let (a, a_files) = device(&dir.0);
+        let (b, b_files) = device(&dir.0);
+
+        let course = add_course(&a, "高数");
+        a.lock().db.record_session(&course, None, &[], "2026-10-01", 1000, 1600).unwrap();
+        a_files.exchange(&a).unwrap();
+        b_files.exchange(&b).unwrap();
+        assert_eq!(b.lock().db.get_course(&course).unwrap().name, "高数");
+        assert_eq!(b.lock().db.get_studied_duration("2026-10-01"), 600);
+
+        b.lock().db.record_session(&course, None, &[], "2026-10-01", 2000, 2300).unwrap();
+        b_files.exchange(&b).unwrap();
+        a_files.exchange(&a).unwrap();
+        assert_eq!(a.lock().db.get_studied_duration("2026-10-01"), 900);
+        assert_eq!(a.lock().db.count_sync_conflicts(), 0);
+        assert_eq!(b.lock().db.count_sync_conflicts(), 0);
+    }
+
+    #[test]
+    fn relayed_own_version_is_not_a_conflict() {
+        let dir = TempDir::new();
+        let (a, a_files) = device(&dir.0);
+        let (b, b_files) = device(&dir.0);
+
+        let course = add_course(&a, "高数");
+        a_files.exchange(&a).unwrap();
+        b_files.exchange(&b).unwrap();
+        // B 的文件中是 A 上次导出的版本，A 之后的修改不应与它冲突
+        let subject_id = a.lock().db.get_subjects()[0].id.clone();
+        a.lock().db.update_course(&course, "高等数学", &subject_id, "", "", "").unwrap();
+        a_files.exchange(&a).unwrap();
+        assert_eq!(a.lock().db.count_sync_conflicts(), 0);
+        assert_eq!(a.lock().db.get_course(&course).unwrap().name, "高等数学");
+    }
+
+    #[test]
+    fn deletions_propagate_and_stay_deleted() {
+        let dir = TempDir::new();
+        let (a, a_files) = device(&dir.0);
+        let (b, b_files) = device(&dir.0);
+
+        let course = add_course(&a, "高数");
+        a_files.exchange(&a).unwrap();
+        b_files.exchange(&b).unwrap();
+        assert!(b.lock().db.get_course(&course).is_some());
+        // B 在删除前导出的文件，包含该课程
+        let stale = b_files.store.read(&b_files.own).unwrap().unwrap();
+
+        a.lock().db.delete_course(&course);
+        a_files.exchange(&a).unwrap();
+        b_files.exchange(&b).unwrap();
+        assert!(b.lock().db.get_course(&course).is_none());
+
+        // A 重新读取 B 删除前的文件（如网盘延迟同步回旧版本），删除时间更晚，课程不会恢复
+        b_files.store.write(&b_files.own, &stale).unwrap();
+        a.lock().db.set_setting("sync_seen_files", "");
+        a_files.exchange(&a).unwrap();
+        assert!(a.lock().db.get_course(&course).is_none());
+    }
+
+    #[test]
+    fn unchanged_data_does_not_rewrite_own_file() {
+        let dir = TempDir::new();
+        let (a, a_files) = device(&dir.0);
+
+        add_course(&a, "高数");
+        a_files.exchange(&a).unwrap();
+        let first = exported_at(&a_files);
+        std::thread::sleep(Duration::from_millis(5));
+        a_files.exchange(&a).unwrap();
+        assert_eq!(exported_at(&a_files), first);
+
+        add_course(&a, "线代");
+        a_files.exchange(&a).unwrap();
+        assert!(exported_at(&a_files) > first);
+    }
+
+    #[test]
+    fn file_encrypted_with_unknown_key() {
+        let dir = TempDir::new();
+        let (b, b_files) = device(&dir.0);
+        let write = |created_at: i64| {
+            let key = crypto::new_key();
+            let name = file_name("other");
+            let wrapped = json!({ "key_id": crypto::key_id(&key), "created_at": created_at });
+            let data = crypto::encrypt(&key, &wrapped, &name, &json!({ "courses": [] }));
+            let file = json!({ "format": FILE_FORMAT, "version": FILE_VERSION, "device_id": "other", "exported_at": created_at, "data": data });
+            Folder { dir: dir.0.clone() }.write(&name, file.to_string().as_bytes()).unwrap();
+        };
+
+        write(200);
+        assert_eq!(b_files.exchange(&b).unwrap_err(), ENCRYPTED_ELSEWHERE);
+
+        let key = crypto::new_key();
+        b.lock().db.set_setting("sync_key", &crypto::encode_key(&key));
+        b.lock().db.set_setting("sync_wrapped_key", &json!({ "key_id": crypto::key_id(&key), "created_at": 100 }).to_string());
+        // 其它设备更换了密钥（密码设置得更晚）时需要重新输入密码
+        assert!(b_files.exchange(&b).unwrap_err().contains("同步密钥已在其它设备上更换"));
+        // 仍在使用更早密钥的设备先跳过
+        write(50);
+        assert!(b_files.exchange(&b).is_ok());
+    }
+
+    #[test]
+    fn hrefs_with_any_namespace_prefix() {
+        let lower = r#"<d:multistatus xmlns:d="DAV:"><d:response><d:href>/dav/device-a.json</d:href></d:response></d:multistatus>"#;
+        let upper = r#"<D:multistatus xmlns:D="DAV:"><D:response><D:href>/dav/device-b.json</D:href></D:response></D:multistatus>"#;
+        let plain = r#"<multistatus xmlns="DAV:"><response><href>
+            /dav/device-c.json
+        </href></response><response><href>/dav/</href></response></multistatus>"#;
+        assert_eq!(hrefs(lower), vec!["/dav/device-a.json"]);
+        assert_eq!(hrefs(upper), vec!["/dav/device-b.json"]);
+        assert_eq!(hrefs(plain), vec!["/dav/device-c.json", "/dav/"]);
+    }
+}
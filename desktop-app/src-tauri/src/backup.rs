@@ -0,0 +1,79 @@
+use rusqlite::types::{Value, ValueRef};
+use std::path::{Path, PathBuf};
+
+// 备份文件格式标识和版本，格式本身变化时递增
+pub const FORMAT: &str = "study-monitor-backup";
+pub const VERSION: i64 = 1;
+
+// 自动备份目录，与数据库文件在同一目录
+pub const BACKUP_DIR: &str = "backups";
+const FILE_PREFIX: &str = "study-monitor-";
+
+pub fn is_valid_mode(mode: &str) -> bool {
+    matches!(mode, "merge" | "replace")
+}
+
+// 全文索引由原表生成，不需要备份
+pub fn is_backup_table(name: &str) -> bool {
+    !name.starts_with("sqlite_") && !name.starts_with("search_index")
+}
+
+pub fn to_json(value: ValueRef) -> serde_json::Value {
+    match value {
+        ValueRef::Null => serde_json::Value::Null,
+        ValueRef::Integer(i) => i.into(),
+        ValueRef::Real(f) => f.into(),
+        ValueRef::Text(t) => String::from_utf8_lossy(t).into(),
+        ValueRef::Blob(b) => b.to_vec().into(),
+    }
+}
+
+pub fn from_json(value: &serde_json::Value) -> Value {
+    match value {
+        serde_json::Value::Null => Value::Null,
+        serde_json::Value::Bool(b) => Value::Integer(*b as i64),
+        serde_json::Value::Number(n) => match n.as_i64() {
+            Some(i) => Value::Integer(i),
+            None => Value::Real(n.as_f64().unwrap_or(0.0)),
+        },
+        serde_json::Value::String(s) => Value::Text(s.clone()),
+        serde_json::Value::Array(items) => Value::Blob(items.iter().filter_map(|v| v.as_u64().map(|b| b as u8)).collect()),
+        serde_json::Value::Object(_) => Value::Text(value.to_string()),
+    }
+}
+
+pub fn file_name(now: chrono::DateTime<chrono::Local>) -> String {
+    format!("{}{}.json", FILE_PREFIX, now.format("%Y%m%d-%H%M%S"))
+}
+
+// 拉取远端数据前的备份，不参与自动备份的轮换
+pub fn pull_file_name(now: chrono::DateTime<chrono::Local>) -> String {
+    format!("before-pull-{}.json", now.format("%Y%m%d-%H%M%S"))
+}
+
+// 自动备份文件，按文件名（即时间）从新到旧
+pub fn list(dir: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = std::fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|e| e.ok().map(|e| e.path()))
+                .filter(|p| {
+                    p.file_name()
+                        .and_then(|n| n.to_str())
+                        .map(|n| n.starts_with(FILE_PREFIX) && n.ends_with(".json"))
+                        .unwrap_or(false)
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    files.sort();
+    files.reverse();
+    files
+}
+
+// 只保留最新的 keep 个自动备份
+pub fn rotate(dir: &Path, keep: usize) {
+    for old in list(dir).into_iter().skip(keep) {
+        let _ = std::fs::remove_file(old);
+    }
+}
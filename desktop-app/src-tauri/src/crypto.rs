@@ -0,0 +1,154 @@
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+// 加密同步数据的格式标识和版本，格式本身变化时递增
+pub const FORMAT: &str = "study-monitor-encrypted";
+pub const VERSION: i64 = 1;
+
+pub const MIN_PASSPHRASE_CHARS: usize = 8;
+
+// Argon2id：64 MiB 内存，3 次迭代；读取远端参数时限制内存、迭代次数和并行度的上限
+const ARGON2_MEMORY_KIB: u32 = 64 * 1024;
+const ARGON2_ITERATIONS: u32 = 3;
+const ARGON2_LANES: u32 = 1;
+const ARGON2_MAX_MEMORY_KIB: u32 = 1024 * 1024;
+const ARGON2_MAX_ITERATIONS: u32 = 16;
+const ARGON2_MAX_LANES: u32 = 16;
+const SALT_BYTES: usize = 16;
+const WRAP_AAD: &[u8] = b"study-monitor-key";
+
+// 数据密钥，随机生成；用同步密码加密后随数据一起上传
+pub type Key = [u8; 32];
+
+pub fn new_key() -> Key {
+    XChaCha20Poly1305::generate_key(&mut OsRng).into()
+}
+
+pub fn encode_key(key: &Key) -> String {
+    STANDARD.encode(key)
+}
+
+pub fn decode_key(text: &str) -> Option<Key> {
+    STANDARD.decode(text).ok()?.try_into().ok()
+}
+
+// 数据密钥的标识，用于判断远端是否已更换密钥，不泄露密钥本身
+pub fn key_id(key: &Key) -> String {
+    Sha256::digest(key)[..8].iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn check_passphrase(passphrase: &str) -> Result<(), String> {
+    if passphrase.chars().count() < MIN_PASSPHRASE_CHARS {
+        return Err(format!("同步密码至少 {} 个字符", MIN_PASSPHRASE_CHARS));
+    }
+    Ok(())
+}
+
+fn derive(passphrase: &str, salt: &[u8], memory: u32, iterations: u32, lanes: u32) -> Result<Key, String> {
+    let params = Params::new(memory, iterations, lanes, Some(32)).map_err(|e| e.to_string())?;
+    let mut key = [0u8; 32];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| e.to_string())?;
+    Ok(key)
+}
+
+fn seal(key: &Key, plaintext: &[u8], aad: &[u8]) -> Value {
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = XChaCha20Poly1305::new(key.into())
+        .encrypt(&nonce, Payload { msg: plaintext, aad })
+        .expect("加密失败");
+    json!({ "nonce": STANDARD.encode(nonce), "ciphertext": STANDARD.encode(ciphertext) })
+}
+
+// 解密失败（密钥错误或数据被篡改）时返回 None
+fn open(key: &Key, sealed: &Value, aad: &[u8]) -> Option<Vec<u8>> {
+    let nonce = STANDARD.decode(sealed["nonce"].as_str()?).ok()?;
+    let ciphertext = STANDARD.decode(sealed["ciphertext"].as_str()?).ok()?;
+    if nonce.len() != 24 {
+        return None;
+    }
+    XChaCha20Poly1305::new(key.into())
+        .decrypt(XNonce::from_slice(&nonce), Payload { msg: &ciphertext, aad })
+        .ok()
+}
+
+// 用同步密码加密数据密钥，created_at 用于各设备采用最新设置的密码
+pub fn wrap_key(key: &Key, passphrase: &str) -> Result<Value, String> {
+    let mut salt = [0u8; SALT_BYTES];
+    OsRng.fill_bytes(&mut salt);
+    let kek = derive(passphrase, &salt, ARGON2_MEMORY_KIB, ARGON2_ITERATIONS, ARGON2_LANES)?;
+    Ok(json!({
+        "kdf": "argon2id",
+        "salt": STANDARD.encode(salt),
+        "memory": ARGON2_MEMORY_KIB,
+        "iterations": ARGON2_ITERATIONS,
+        "lanes": ARGON2_LANES,
+        "key_id": key_id(key),
+        "created_at": chrono::Utc::now().timestamp_millis(),
+        "key": seal(&kek, key, WRAP_AAD),
+    }))
+}
+
+pub fn unwrap_key(wrapped: &Value, passphrase: &str) -> Result<Key, String> {
+    let param = |name: &str| wrapped[name].as_u64().and_then(|v| u32::try_from(v).ok());
+    let (Some(memory), Some(iterations), Some(lanes)) = (param("memory"), param("iterations"), param("lanes")) else {
+        return Err("不支持的密钥格式".to_string());
+    };
+    if wrapped["kdf"] != "argon2id"
+        || memory > ARGON2_MAX_MEMORY_KIB
+        || iterations > ARGON2_MAX_ITERATIONS
+        || lanes > ARGON2_MAX_LANES
+    {
+        return Err("不支持的密钥格式".to_string());
+    }
+    let salt = wrapped["salt"]
+        .as_str()
+        .and_then(|s| STANDARD.decode(s).ok())
+        .ok_or("不支持的密钥格式")?;
+    let kek = derive(passphrase, &salt, memory, iterations, lanes)?;
+    open(&kek, &wrapped["key"], WRAP_AAD)
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| "同步密码错误".to_string())
+}
+
+// 加密后的同步数据；用户 ID 作为附加数据参与认证，数据不能被移到其它用户下使用
+pub fn encrypt(key: &Key, wrapped: &Value, user_id: &str, data: &Value) -> Value {
+    json!({
+        "format": FORMAT,
+        "version": VERSION,
+        "key_id": key_id(key),
+        "wrapped_key": wrapped,
+        "data": seal(key, data.to_string().as_bytes(), user_id.as_bytes()),
+    })
+}
+
+// keys 为本机已知的数据密钥（当前的和更换前的），按 key_id 选用
+pub fn decrypt(keys: &[Key], user_id: &str, envelope: &Value) -> Result<Value, String> {
+    if envelope["format"] != FORMAT || envelope["version"].as_i64().unwrap_or(i64::MAX) > VERSION {
+        return Err("无法识别的加密数据，请升级应用".to_string());
+    }
+    let key = keys
+        .iter()
+        .find(|k| envelope["key_id"] == key_id(k))
+        .ok_or("同步密钥已在其它设备上更换，请重新输入同步密码")?;
+    let plaintext = open(key, &envelope["data"], user_id.as_bytes()).ok_or("解密失败，数据可能已损坏")?;
+    serde_json::from_slice(&plaintext).map_err(|e| format!("无效的同步数据：{}", e))
+}
+
+// 恢复短语：数据密钥的 BIP39 助记词（24 个英文单词）
+pub fn recovery_phrase(key: &Key) -> String {
+    bip39::Mnemonic::from_entropy(key).expect("32 字节熵").to_string()
+}
+
+pub fn key_from_phrase(phrase: &str) -> Result<Key, String> {
+    let normalized = phrase.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase();
+    let mnemonic = bip39::Mnemonic::parse_normalized(&normalized).map_err(|_| "恢复短语无效".to_string())?;
+    mnemonic.to_entropy().try_into().map_err(|_| "恢复短语无效".to_string())
+}
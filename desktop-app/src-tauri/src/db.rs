@@ -1,14 +1,161 @@
-use crate::{Course, CourseStat, DailyStat, Statistics, StudyLog};
-use rusqlite::{Connection, params};
+use crate::{aggregation, backup, export, goals, heatmap, ics, import, merge, planner, report, schedule, search, streaks};
+use crate::{
+    AdherenceReport, Chapter, ChapterStat, Course, CourseStat, DailyStat, Exam, ExamCountdown, ExamPlan, ExportLog,
+    ExportSession, Goal, GoalProgress, GoalSnapshot, ImportPreview, ImportResult, ImportRow, PlannedBlock, Report, ReportSubject, SearchHit,
+    Statistics, Streaks, StudyLog, StudySession, Subject, SubjectPlan, SyncConflict, SyncPreview, Tag, TagStat, TimeHeatmap,
+};
+use chrono::NaiveDate;
+use std::collections::HashMap;
+use rusqlite::{Connection, Row, params};
 use uuid::Uuid;
 
+fn row_to_course(row: &Row) -> rusqlite::Result<Course> {
+    Ok(Course {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        subject: row.get(2)?,
+        url_pattern: row.get(3)?,
+        app_pattern: row.get(4)?,
+        title_pattern: row.get(5)?,
+        subject_id: row.get::<_, Option<String>>(6)?.unwrap_or_default(),
+    })
+}
+
+fn row_to_session(row: &Row) -> rusqlite::Result<StudySession> {
+    Ok(StudySession {
+        id: row.get(0)?,
+        course_id: row.get(1)?,
+        chapter_id: row.get(2)?,
+        date: row.get(3)?,
+        start_time: row.get(4)?,
+        end_time: row.get(5)?,
+        duration: row.get(6)?,
+        note: row.get(7)?,
+        focus: row.get(8)?,
+        titles: row.get(9)?,
+    })
+}
+
+fn row_to_subject(row: &Row) -> rusqlite::Result<Subject> {
+    Ok(Subject {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        color: row.get(2)?,
+        parent_id: row.get(3)?,
+        sort_order: row.get(4)?,
+    })
+}
+
+// history 按生效日期升序，取不晚于 date 的最后一条
+fn goal_on(history: &[GoalSnapshot], date: &str) -> Option<i64> {
+    history
+        .iter()
+        .take_while(|g| g.effective_date.as_str() <= date)
+        .last()
+        .map(|g| g.seconds)
+}
+
+// 旧数据库升级：缺少的列用 ALTER TABLE 补上
+fn add_column_if_missing(conn: &Connection, table: &str, column: &str, decl: &str) -> Result<(), rusqlite::Error> {
+    let exists = conn
+        .prepare(&format!("PRAGMA table_info({})", table))?
+        .query_map([], |row| row.get::<_, String>(1))?
+        .filter_map(|r| r.ok())
+        .any(|name| name == column);
+    if !exists {
+        conn.execute(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, decl), [])?;
+    }
+    Ok(())
+}
+
+// 统计用的学习记录来源，?4 为标签名：未指定标签时即 study_logs；
+// 指定时取带该标签课程的全部记录，加上其他课程中带该标签的会话
+const TAGGED_LOGS: &str = "(
+    SELECT course_id, date, duration FROM study_logs
+    WHERE ?4 IS NULL OR course_id IN (
+        SELECT ct.course_id FROM course_tags ct JOIN tags t ON ct.tag_id = t.id WHERE t.name = ?4
+    )
+    UNION ALL
+    SELECT s.course_id, s.date, s.duration FROM sessions s
+    WHERE ?4 IS NOT NULL
+      AND s.id IN (SELECT st.session_id FROM session_tags st JOIN tags t ON st.tag_id = t.id WHERE t.name = ?4)
+      AND s.course_id NOT IN (SELECT ct.course_id FROM course_tags ct JOIN tags t ON ct.tag_id = t.id WHERE t.name = ?4)
+)";
+
+// 科目及其全部下级科目的 id 集合，id 为 SQL 表达式（参数或子查询）
+fn subject_tree(id: &str) -> String {
+    format!(
+        "(WITH RECURSIVE tree(id) AS (
+            SELECT {}
+            UNION SELECT s.id FROM subjects s JOIN tree ON s.parent_id = tree.id
+        ) SELECT id FROM tree)",
+        id
+    )
+}
+
+// 从原表生成全文索引
+const SEARCH_INDEX_FILL: &str = "
+    INSERT INTO search_index (kind, ref_id, name, body) SELECT 'course', id, name, subject FROM courses;
+    INSERT INTO search_index (kind, ref_id, name, body) SELECT 'chapter', id, name, '' FROM chapters;
+    INSERT INTO search_index (kind, ref_id, name, body)
+    SELECT 'session', id, note, titles FROM sessions WHERE note != '' OR titles != '';
+";
+
+// 数据库结构版本，保存在 PRAGMA user_version，表结构变化时递增
+pub const SCHEMA_VERSION: i64 = 3;
+
+// 参与增量同步的表及其主键
+const SYNC_TABLES: [(&str, &str); 4] = [("courses", "id"), ("study_logs", "id"), ("sessions", "id"), ("settings", "key")];
+
+// 只属于本机的设置，不写入备份，恢复备份时保留本机的值
+const LOCAL_SETTINGS: [&str; 7] =
+    ["device_id", "sync_key", "sync_previous_key", "sync_wrapped_key", "calendar_token", "webdav_password", "sync_token"];
+
+// 当前时间（毫秒），与 chrono::Utc::now().timestamp_millis() 一致
+const NOW_MS: &str = "CAST((julianday('now') - 2440587.5) * 86400000 AS INTEGER)";
+
+// 修改时间和删除记录由触发器维护：未指定 updated_at 的写入记为当前时间；
+// 合并远端数据期间 sync_applying 中有记录，写入的行保留远端的 updated_at（缺少时为 0），
+// 不会被当作本机修改再次上传
+fn sync_triggers(table: &str, key: &str) -> String {
+    format!(
+        "DROP TRIGGER IF EXISTS sync_{t}_insert;
+        DROP TRIGGER IF EXISTS sync_{t}_update;
+        CREATE TRIGGER sync_{t}_insert AFTER INSERT ON {t} BEGIN
+            UPDATE {t} SET updated_at = {now}
+            WHERE {k} = new.{k} AND new.updated_at = 0 AND NOT EXISTS (SELECT 1 FROM sync_applying);
+            DELETE FROM sync_tombstones WHERE table_name = '{t}' AND row_id = new.{k};
+        END;
+        CREATE TRIGGER sync_{t}_update AFTER UPDATE ON {t}
+        WHEN new.updated_at = old.updated_at AND NOT EXISTS (SELECT 1 FROM sync_applying) BEGIN
+            UPDATE {t} SET updated_at = {now} WHERE {k} = new.{k};
+        END;
+        CREATE TRIGGER IF NOT EXISTS sync_{t}_delete AFTER DELETE ON {t} BEGIN
+            INSERT OR REPLACE INTO sync_tombstones (table_name, row_id, deleted_at) VALUES ('{t}', old.{k}, {now});
+        END;",
+        t = table,
+        k = key,
+        now = NOW_MS
+    )
+}
+
 pub struct Database {
     conn: Connection,
 }
 
 impl Database {
     pub fn new() -> Result<Self, rusqlite::Error> {
-        let conn = Connection::open("study_monitor.db")?;
+        Self::open(Connection::open("study_monitor.db")?)
+    }
+
+    #[cfg(test)]
+    pub fn in_memory() -> Result<Self, rusqlite::Error> {
+        Self::open(Connection::open_in_memory()?)
+    }
+
+    fn open(conn: Connection) -> Result<Self, rusqlite::Error> {
+        // 打开前的结构版本，只需执行一次的迁移据此判断
+        let version: i64 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
         
         conn.execute(
             "CREATE TABLE IF NOT EXISTS courses (
@@ -44,22 +191,295 @@ impl Database {
             [],
         )?;
 
-        Ok(Self { conn })
+        // 每次学习会话的起止时间（Unix 时间戳），date 与 study_logs 中记入的日期一致
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS sessions (
+                id TEXT PRIMARY KEY,
+                course_id TEXT NOT NULL,
+                date TEXT NOT NULL,
+                start_time INTEGER NOT NULL,
+                end_time INTEGER NOT NULL,
+                duration INTEGER NOT NULL,
+                FOREIGN KEY (course_id) REFERENCES courses(id)
+            )",
+            [],
+        )?;
+
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_sessions_date ON sessions(date)",
+            [],
+        )?;
+
+        // 分科目 / 分课程目标，scope 为 subject 时 target 是科目名，为 course 时是课程 id
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS goals (
+                id TEXT PRIMARY KEY,
+                scope TEXT NOT NULL,
+                target TEXT NOT NULL,
+                period TEXT NOT NULL,
+                seconds INTEGER NOT NULL
+            )",
+            [],
+        )?;
+
+        // 考试，exam_targets 中 scope 为 subject 时 target 是科目名，为 course 时是课程 id
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS exams (
+                id TEXT PRIMARY KEY,
+                name TEXT NOT NULL,
+                date TEXT NOT NULL,
+                target_seconds INTEGER NOT NULL DEFAULT 0
+            )",
+            [],
+        )?;
+
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS exam_targets (
+                exam_id TEXT NOT NULL,
+                scope TEXT NOT NULL,
+                target TEXT NOT NULL,
+                PRIMARY KEY (exam_id, scope, target)
+            )",
+            [],
+        )?;
+
+        // 旧版本只有一个 exam_date 设置，迁移为默认考试；只在记录结构版本之前的数据库上执行一次，
+        // 否则删除全部考试后下次启动又会从旧设置恢复
+        if version == 0 {
+            conn.execute(
+                "INSERT OR IGNORE INTO exams (id, name, date, target_seconds)
+                 SELECT 'default', '考试', value, 0 FROM settings
+                 WHERE key = 'exam_date' AND value != '' AND NOT EXISTS (SELECT 1 FROM exams)",
+                [],
+            )?;
+        }
+
+        // 学习计划：weekday 为每周重复（0 = 周一），date 为单次计划，时间格式 HH:MM
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS planned_blocks (
+                id TEXT PRIMARY KEY,
+                course_id TEXT NOT NULL,
+                weekday INTEGER,
+                date TEXT,
+                start_time TEXT NOT NULL,
+                end_time TEXT NOT NULL,
+                FOREIGN KEY (course_id) REFERENCES courses(id)
+            )",
+            [],
+        )?;
+
+        // 考前各科目需要完成的总学习时长
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS subject_targets (
+                subject TEXT PRIMARY KEY,
+                seconds INTEGER NOT NULL
+            )",
+            [],
+        )?;
+
+        // 每日目标的生效历史，过去的日期按当时生效的目标判断
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS goal_history (
+                effective_date TEXT PRIMARY KEY,
+                seconds INTEGER NOT NULL
+            )",
+            [],
+        )?;
+
+        // 课程章节，url_pattern 匹配页面 URL 或窗口标题，用于按章节记录时长
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS chapters (
+                id TEXT PRIMARY KEY,
+                course_id TEXT NOT NULL,
+                name TEXT NOT NULL,
+                sort_order INTEGER NOT NULL,
+                estimated_seconds INTEGER NOT NULL DEFAULT 0,
+                completed INTEGER NOT NULL DEFAULT 0,
+                url_pattern TEXT NOT NULL DEFAULT '',
+                FOREIGN KEY (course_id) REFERENCES courses(id)
+            )",
+            [],
+        )?;
+
+        // 科目，parent_id 指向上级科目；courses.subject 保留科目名，随科目改名同步更新
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS subjects (
+                id TEXT PRIMARY KEY,
+                name TEXT NOT NULL UNIQUE,
+                color TEXT NOT NULL DEFAULT '',
+                parent_id TEXT,
+                sort_order INTEGER NOT NULL DEFAULT 0
+            )",
+            [],
+        )?;
+
+        // 已导入的外部记录，用于识别重复导入
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS import_entries (
+                key TEXT PRIMARY KEY,
+                imported_at INTEGER NOT NULL
+            )",
+            [],
+        )?;
+
+        // 标签，可同时关联课程和单次会话
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS tags (
+                id TEXT PRIMARY KEY,
+                name TEXT NOT NULL UNIQUE
+            )",
+            [],
+        )?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS course_tags (
+                course_id TEXT NOT NULL,
+                tag_id TEXT NOT NULL,
+                PRIMARY KEY (course_id, tag_id)
+            )",
+            [],
+        )?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS session_tags (
+                session_id TEXT NOT NULL,
+                tag_id TEXT NOT NULL,
+                PRIMARY KEY (session_id, tag_id)
+            )",
+            [],
+        )?;
+
+        // 桌面应用规则：应用名（WM_CLASS）和窗口标题
+        add_column_if_missing(&conn, "courses", "app_pattern", "TEXT NOT NULL DEFAULT ''")?;
+        add_column_if_missing(&conn, "courses", "title_pattern", "TEXT NOT NULL DEFAULT ''")?;
+        add_column_if_missing(&conn, "sessions", "chapter_id", "TEXT")?;
+        add_column_if_missing(&conn, "courses", "subject_id", "TEXT")?;
+        // 会话备注和自评专注度（1-5）
+        add_column_if_missing(&conn, "sessions", "note", "TEXT NOT NULL DEFAULT ''")?;
+        add_column_if_missing(&conn, "sessions", "focus", "INTEGER")?;
+        // 会话期间访问的页面 / 窗口标题，每行一个
+        add_column_if_missing(&conn, "sessions", "titles", "TEXT NOT NULL DEFAULT ''")?;
+
+        // 多设备合并：会话和每日记录属于记录它的设备，各设备的时长分别累加；旧记录为空
+        add_column_if_missing(&conn, "sessions", "device_id", "TEXT NOT NULL DEFAULT ''")?;
+        add_column_if_missing(&conn, "study_logs", "device_id", "TEXT NOT NULL DEFAULT ''")?;
+
+        // 增量同步：每行的修改时间（毫秒）和删除记录
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS sync_tombstones (
+                table_name TEXT NOT NULL,
+                row_id TEXT NOT NULL,
+                deleted_at INTEGER NOT NULL,
+                PRIMARY KEY (table_name, row_id)
+            )",
+            [],
+        )?;
+        // 正在合并远端数据的标记，只在合并的事务中有一行
+        conn.execute("CREATE TABLE IF NOT EXISTS sync_applying (active INTEGER NOT NULL)", [])?;
+        for (table, key) in SYNC_TABLES {
+            add_column_if_missing(&conn, table, "updated_at", "INTEGER NOT NULL DEFAULT 0")?;
+            // 升级前的数据记为现在修改，下次同步时上传；只在升级到版本 3 时执行一次。
+            // 之后 updated_at = 0 的行只来自远端缺少修改时间的数据，合并时触发器不改写，保持为 0
+            if version < 3 {
+                conn.execute(&format!("UPDATE {} SET updated_at = {} WHERE updated_at = 0", table, NOW_MS), [])?;
+            }
+            conn.execute_batch(&sync_triggers(table, key))?;
+        }
+        // 同步时双方都修改过的记录，按规则合并后保留另一方的版本供用户查看
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS sync_conflicts (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                table_name TEXT NOT NULL,
+                row_id TEXT NOT NULL,
+                local TEXT NOT NULL,
+                remote TEXT NOT NULL,
+                kept TEXT NOT NULL,
+                detected_at INTEGER NOT NULL
+            )",
+            [],
+        )?;
+
+        // 全文索引：课程名和科目、章节名、会话备注和页面标题，由触发器随原表更新
+        let index_exists: bool = conn
+            .query_row("SELECT COUNT(*) FROM sqlite_master WHERE name = 'search_index'", [], |row| row.get::<_, i64>(0))
+            .map(|n| n > 0)?;
+        conn.execute_batch(
+            "CREATE VIRTUAL TABLE IF NOT EXISTS search_index USING fts5(
+                kind UNINDEXED, ref_id UNINDEXED, name, body, tokenize = 'trigram'
+            );
+            CREATE TRIGGER IF NOT EXISTS search_courses_insert AFTER INSERT ON courses BEGIN
+                INSERT INTO search_index (kind, ref_id, name, body) VALUES ('course', new.id, new.name, new.subject);
+            END;
+            CREATE TRIGGER IF NOT EXISTS search_courses_update AFTER UPDATE OF name, subject ON courses BEGIN
+                DELETE FROM search_index WHERE kind = 'course' AND ref_id = old.id;
+                INSERT INTO search_index (kind, ref_id, name, body) VALUES ('course', new.id, new.name, new.subject);
+            END;
+            CREATE TRIGGER IF NOT EXISTS search_courses_delete AFTER DELETE ON courses BEGIN
+                DELETE FROM search_index WHERE kind = 'course' AND ref_id = old.id;
+            END;
+            CREATE TRIGGER IF NOT EXISTS search_chapters_insert AFTER INSERT ON chapters BEGIN
+                INSERT INTO search_index (kind, ref_id, name, body) VALUES ('chapter', new.id, new.name, '');
+            END;
+            CREATE TRIGGER IF NOT EXISTS search_chapters_update AFTER UPDATE OF name ON chapters BEGIN
+                DELETE FROM search_index WHERE kind = 'chapter' AND ref_id = old.id;
+                INSERT INTO search_index (kind, ref_id, name, body) VALUES ('chapter', new.id, new.name, '');
+            END;
+            CREATE TRIGGER IF NOT EXISTS search_chapters_delete AFTER DELETE ON chapters BEGIN
+                DELETE FROM search_index WHERE kind = 'chapter' AND ref_id = old.id;
+            END;
+            CREATE TRIGGER IF NOT EXISTS search_sessions_insert AFTER INSERT ON sessions
+            WHEN new.note != '' OR new.titles != '' BEGIN
+                INSERT INTO search_index (kind, ref_id, name, body) VALUES ('session', new.id, new.note, new.titles);
+            END;
+            CREATE TRIGGER IF NOT EXISTS search_sessions_update AFTER UPDATE OF note, titles ON sessions BEGIN
+                DELETE FROM search_index WHERE kind = 'session' AND ref_id = old.id;
+                INSERT INTO search_index (kind, ref_id, name, body)
+                SELECT 'session', new.id, new.note, new.titles WHERE new.note != '' OR new.titles != '';
+            END;
+            CREATE TRIGGER IF NOT EXISTS search_sessions_delete AFTER DELETE ON sessions BEGIN
+                DELETE FROM search_index WHERE kind = 'session' AND ref_id = old.id;
+            END;",
+        )?;
+        // 首次建立索引时导入已有数据
+        if !index_exists {
+            conn.execute_batch(SEARCH_INDEX_FILL)?;
+        }
+        conn.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+
+        let db = Self { conn };
+
+        // 旧数据中的科目名迁移为科目记录
+        let legacy: Vec<String> = db.conn
+            .prepare("SELECT DISTINCT subject FROM courses WHERE subject_id IS NULL")?
+            .query_map([], |row| row.get(0))?
+            .filter_map(|r| r.ok())
+            .collect();
+        for name in legacy {
+            let subject_id = db.ensure_subject(&name);
+            db.conn.execute(
+                "UPDATE courses SET subject_id = ? WHERE subject_id IS NULL AND subject = ?",
+                params![subject_id, name],
+            )?;
+        }
+
+        // 首次升级时用当前目标作为最早的一条历史
+        let history_empty: bool = db.conn
+            .query_row("SELECT COUNT(*) FROM goal_history", [], |row| row.get::<_, i64>(0))
+            .map(|n| n == 0)?;
+        if history_empty {
+            db.conn.execute(
+                "INSERT INTO goal_history (effective_date, seconds) VALUES ('1970-01-01', ?)",
+                [db.get_daily_goal()],
+            )?;
+        }
+
+        Ok(db)
     }
 
     pub fn get_courses(&self) -> Vec<Course> {
         let mut stmt = self.conn
-            .prepare("SELECT id, name, subject, url_pattern FROM courses")
+            .prepare("SELECT id, name, subject, url_pattern, app_pattern, title_pattern, subject_id FROM courses")
             .unwrap();
         
-        stmt.query_map([], |row| {
-            Ok(Course {
-                id: row.get(0)?,
-                name: row.get(1)?,
-                subject: row.get(2)?,
-                url_pattern: row.get(3)?,
-            })
-        })
+        stmt.query_map([], row_to_course)
         .unwrap()
         .filter_map(|r| r.ok())
         .collect()
@@ -68,231 +488,1329 @@ impl Database {
     pub fn get_course(&self, id: &str) -> Option<Course> {
         self.conn
             .query_row(
-                "SELECT id, name, subject, url_pattern FROM courses WHERE id = ?",
+                "SELECT id, name, subject, url_pattern, app_pattern, title_pattern, subject_id FROM courses WHERE id = ?",
                 [id],
-                |row| {
-                    Ok(Course {
-                        id: row.get(0)?,
-                        name: row.get(1)?,
-                        subject: row.get(2)?,
-                        url_pattern: row.get(3)?,
-                    })
-                },
+                row_to_course,
             )
             .ok()
     }
 
-    pub fn add_course(&self, name: &str, subject: &str, url_pattern: &str) {
+    pub fn add_course(&self, name: &str, subject_id: &str, url_pattern: &str, app_pattern: &str, title_pattern: &str) -> Result<String, String> {
+        let subject = self.subject_name(subject_id)?;
         let id = Uuid::new_v4().to_string();
         self.conn
             .execute(
-                "INSERT INTO courses (id, name, subject, url_pattern) VALUES (?, ?, ?, ?)",
-                params![id, name, subject, url_pattern],
+                "INSERT INTO courses (id, name, subject, url_pattern, app_pattern, title_pattern, subject_id) VALUES (?, ?, ?, ?, ?, ?, ?)",
+                params![id, name, subject, url_pattern, app_pattern, title_pattern, subject_id],
             )
-            .unwrap();
+            .map_err(|e| e.to_string())?;
+        Ok(id)
     }
 
-    pub fn update_course(&self, id: &str, name: &str, subject: &str, url_pattern: &str) {
+    pub fn update_course(&self, id: &str, name: &str, subject_id: &str, url_pattern: &str, app_pattern: &str, title_pattern: &str) -> Result<(), String> {
+        let subject = self.subject_name(subject_id)?;
         self.conn
             .execute(
-                "UPDATE courses SET name = ?, subject = ?, url_pattern = ? WHERE id = ?",
-                params![name, subject, url_pattern, id],
+                "UPDATE courses SET name = ?, subject = ?, url_pattern = ?, app_pattern = ?, title_pattern = ?, subject_id = ? WHERE id = ?",
+                params![name, subject, url_pattern, app_pattern, title_pattern, subject_id, id],
             )
-            .unwrap();
+            .map_err(|e| e.to_string())?;
+        Ok(())
     }
 
+    // 先删除引用课程的记录，外键约束下最后删除课程本身
     pub fn delete_course(&self, id: &str) {
-        self.conn.execute("DELETE FROM courses WHERE id = ?", [id]).unwrap();
         self.conn.execute("DELETE FROM study_logs WHERE course_id = ?", [id]).unwrap();
+        self.conn.execute("DELETE FROM sessions WHERE course_id = ?", [id]).unwrap();
+        self.conn.execute("DELETE FROM goals WHERE scope = 'course' AND target = ?", [id]).unwrap();
+        self.conn.execute("DELETE FROM exam_targets WHERE scope = 'course' AND target = ?", [id]).unwrap();
+        self.conn.execute("DELETE FROM planned_blocks WHERE course_id = ?", [id]).unwrap();
+        self.conn.execute("DELETE FROM chapters WHERE course_id = ?", [id]).unwrap();
+        self.conn.execute("DELETE FROM course_tags WHERE course_id = ?", [id]).unwrap();
+        self.conn
+            .execute("DELETE FROM session_tags WHERE session_id NOT IN (SELECT id FROM sessions)", [])
+            .unwrap();
+        self.conn.execute("DELETE FROM courses WHERE id = ?", [id]).unwrap();
     }
 
-    pub fn get_daily_goal(&self) -> i64 {
+    pub fn get_subjects(&self) -> Vec<Subject> {
+        let mut stmt = self.conn
+            .prepare("SELECT id, name, color, parent_id, sort_order FROM subjects ORDER BY sort_order, name")
+            .unwrap();
+        stmt.query_map([], row_to_subject)
+            .unwrap()
+            .filter_map(|r| r.ok())
+            .collect()
+    }
+
+    // 课程只能关联已有科目，courses.subject 保存科目名称的冗余副本
+    fn subject_name(&self, subject_id: &str) -> Result<String, String> {
         self.conn
-            .query_row(
-                "SELECT value FROM settings WHERE key = 'daily_goal'",
-                [],
-                |row| row.get::<_, String>(0),
-            )
-            .ok()
-            .and_then(|v| v.parse().ok())
-            .unwrap_or(7200) // 默认 2 小时
+            .query_row("SELECT name FROM subjects WHERE id = ?", [subject_id], |row| row.get(0))
+            .map_err(|_| "科目不存在".to_string())
     }
 
-    pub fn set_daily_goal(&self, seconds: i64) {
+    fn subject_tree_ids(&self, subject_id: &str) -> Vec<String> {
+        self.conn
+            .prepare(&format!("SELECT id FROM {}", subject_tree("?1")))
+            .unwrap()
+            .query_map([subject_id], |row| row.get(0))
+            .unwrap()
+            .filter_map(|r| r.ok())
+            .collect()
+    }
+
+    // 按名称查找科目，不存在时新建，返回科目 id
+    fn ensure_subject(&self, name: &str) -> String {
+        if let Ok(id) = self.conn.query_row("SELECT id FROM subjects WHERE name = ?", [name], |row| row.get(0)) {
+            return id;
+        }
+        let id = Uuid::new_v4().to_string();
         self.conn
             .execute(
-                "INSERT OR REPLACE INTO settings (key, value) VALUES ('daily_goal', ?)",
-                [seconds.to_string()],
+                "INSERT INTO subjects (id, name, color, parent_id, sort_order)
+                 VALUES (?1, ?2, '', NULL, (SELECT COALESCE(MAX(sort_order), -1) + 1 FROM subjects))",
+                params![id, name],
             )
             .unwrap();
+        id
     }
 
-    pub fn get_studied_duration(&self, date: &str) -> i64 {
+    fn subject_name_taken(&self, name: &str, except_id: &str) -> bool {
         self.conn
             .query_row(
-                "SELECT COALESCE(SUM(duration), 0) FROM study_logs WHERE date = ?",
-                [date],
-                |row| row.get(0),
+                "SELECT COUNT(*) FROM subjects WHERE name = ? AND id != ?",
+                params![name, except_id],
+                |row| row.get::<_, i64>(0),
             )
-            .unwrap_or(0)
+            .map(|n| n > 0)
+            .unwrap_or(false)
     }
 
-    pub fn add_study_log(&self, course_id: &str, date: &str, duration: i64) {
-        // 先检查课程是否存在
-        let course_exists: bool = self.conn
-            .query_row(
-                "SELECT 1 FROM courses WHERE id = ?",
-                [course_id],
-                |_| Ok(true),
-            )
-            .unwrap_or(false);
+    // 上级科目必须存在，且不能是自身或自己的下级科目
+    fn is_valid_parent(&self, id: &str, parent_id: Option<&str>) -> bool {
+        let subjects = self.get_subjects();
+        let mut current = parent_id;
+        for _ in 0..=subjects.len() {
+            let Some(p) = current else {
+                return true;
+            };
+            if p == id {
+                return false;
+            }
+            match subjects.iter().find(|s| s.id == p) {
+                Some(s) => current = s.parent_id.as_deref(),
+                None => return false,
+            }
+        }
+        false
+    }
 
-        if !course_exists {
-            return; // 课程不存在，跳过记录
+    pub fn add_subject(&self, name: &str, color: &str, parent_id: Option<&str>) -> Result<(), String> {
+        let id = Uuid::new_v4().to_string();
+        if self.subject_name_taken(name, &id) {
+            return Err("科目名称已存在".to_string());
+        }
+        if !self.is_valid_parent(&id, parent_id) {
+            return Err("无效的上级科目".to_string());
         }
+        self.conn
+            .execute(
+                "INSERT INTO subjects (id, name, color, parent_id, sort_order)
+                 VALUES (?1, ?2, ?3, ?4, (SELECT COALESCE(MAX(sort_order), -1) + 1 FROM subjects))",
+                params![id, name, color, parent_id],
+            )
+            .unwrap();
+        Ok(())
+    }
 
-        // 尝试更新现有记录
-        let updated = self.conn
+    // 改名时同步更新课程、分项目标、考试范围和目标时长中引用的科目名
+    pub fn update_subject(&self, id: &str, name: &str, color: &str, parent_id: Option<&str>, sort_order: i64) -> Result<(), String> {
+        let old_name: String = self.conn
+            .query_row("SELECT name FROM subjects WHERE id = ?", [id], |row| row.get(0))
+            .map_err(|_| "科目不存在".to_string())?;
+        if self.subject_name_taken(name, id) {
+            return Err("科目名称已存在".to_string());
+        }
+        if !self.is_valid_parent(id, parent_id) {
+            return Err("无效的上级科目".to_string());
+        }
+        self.conn
             .execute(
-                "UPDATE study_logs SET duration = duration + ? WHERE course_id = ? AND date = ?",
-                params![duration, course_id, date],
+                "UPDATE subjects SET name = ?, color = ?, parent_id = ?, sort_order = ? WHERE id = ?",
+                params![name, color, parent_id, sort_order, id],
             )
-            .unwrap_or(0);
+            .unwrap();
+        if old_name != name {
+            self.conn.execute("UPDATE courses SET subject = ? WHERE subject_id = ?", params![name, id]).unwrap();
+            self.conn
+                .execute("UPDATE goals SET target = ? WHERE scope = 'subject' AND target = ?", params![name, old_name])
+                .unwrap();
+            self.conn
+                .execute("UPDATE exam_targets SET target = ? WHERE scope = 'subject' AND target = ?", params![name, old_name])
+                .unwrap();
+            self.conn
+                .execute("UPDATE subject_targets SET subject = ? WHERE subject = ?", params![name, old_name])
+                .unwrap();
+        }
+        Ok(())
+    }
 
-        // 如果没有更新到记录，插入新记录
-        if updated == 0 {
-            let id = Uuid::new_v4().to_string();
-            let _ = self.conn
-                .execute(
-                    "INSERT INTO study_logs (id, course_id, date, duration) VALUES (?, ?, ?, ?)",
-                    params![id, course_id, date, duration],
-                );
+    // 仍有课程的科目不能删除，下级科目挂到被删科目的上级
+    pub fn delete_subject(&self, id: &str) -> Result<(), String> {
+        let in_use: i64 = self.conn
+            .query_row("SELECT COUNT(*) FROM courses WHERE subject_id = ?", [id], |row| row.get(0))
+            .unwrap();
+        if in_use > 0 {
+            return Err("该科目下还有课程".to_string());
         }
+        self.conn
+            .execute(
+                "UPDATE subjects SET parent_id = (SELECT parent_id FROM subjects WHERE id = ?1) WHERE parent_id = ?1",
+                [id],
+            )
+            .unwrap();
+        self.conn.execute("DELETE FROM subjects WHERE id = ?", [id]).unwrap();
+        Ok(())
     }
 
+    pub fn get_tags(&self) -> Vec<Tag> {
+        let mut stmt = self.conn.prepare("SELECT id, name FROM tags ORDER BY name").unwrap();
+        stmt.query_map([], |row| Ok(Tag { id: row.get(0)?, name: row.get(1)? }))
+            .unwrap()
+            .filter_map(|r| r.ok())
+            .collect()
+    }
 
-    pub fn get_statistics(
-        &self,
-        start_date: Option<String>,
-        end_date: Option<String>,
-        subject: Option<String>,
-    ) -> Statistics {
-        let start = start_date.unwrap_or_else(|| "1970-01-01".to_string());
-        let end = end_date.unwrap_or_else(|| "2099-12-31".to_string());
+    pub fn add_tag(&self, name: &str) -> Result<Tag, String> {
+        let tag = Tag { id: Uuid::new_v4().to_string(), name: name.to_string() };
+        self.conn
+            .execute("INSERT INTO tags (id, name) VALUES (?, ?)", params![tag.id, tag.name])
+            .map_err(|_| "标签已存在".to_string())?;
+        Ok(tag)
+    }
 
-        // 获取所有科目
-        let mut subjects: Vec<String> = self.conn
-            .prepare("SELECT DISTINCT subject FROM courses")
-            .unwrap()
-            .query_map([], |row| row.get(0))
-            .unwrap()
-            .filter_map(|r: Result<String, _>| r.ok())
-            .collect();
-        subjects.sort();
+    pub fn delete_tag(&self, id: &str) {
+        self.conn.execute("DELETE FROM tags WHERE id = ?", [id]).unwrap();
+        self.conn.execute("DELETE FROM course_tags WHERE tag_id = ?", [id]).unwrap();
+        self.conn.execute("DELETE FROM session_tags WHERE tag_id = ?", [id]).unwrap();
+    }
 
-        // 按课程统计
-        let rows: Vec<(String, String, String, i64)> = if let Some(ref subj) = subject {
-            let mut stmt = self.conn.prepare(
-                "SELECT c.id, c.name, c.subject, COALESCE(SUM(l.duration), 0) as total
-                 FROM courses c
-                 LEFT JOIN study_logs l ON c.id = l.course_id AND l.date BETWEEN ?1 AND ?2
-                 WHERE c.subject = ?3
-                 GROUP BY c.id"
-            ).unwrap();
-            stmt.query_map(params![&start, &end, subj], |row| {
-                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
-            })
+    pub fn get_course_tags(&self, course_id: &str) -> Vec<String> {
+        let mut stmt = self.conn.prepare("SELECT tag_id FROM course_tags WHERE course_id = ?").unwrap();
+        stmt.query_map([course_id], |row| row.get(0))
             .unwrap()
-            .filter_map(|r: Result<(String, String, String, i64), _>| r.ok())
+            .filter_map(|r| r.ok())
             .collect()
-        } else {
-            let mut stmt = self.conn.prepare(
-                "SELECT c.id, c.name, c.subject, COALESCE(SUM(l.duration), 0) as total
-                 FROM courses c
-                 LEFT JOIN study_logs l ON c.id = l.course_id AND l.date BETWEEN ?1 AND ?2
-                 GROUP BY c.id"
-            ).unwrap();
-            stmt.query_map(params![&start, &end], |row| {
-                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
-            })
+    }
+
+    pub fn set_course_tags(&self, course_id: &str, tag_ids: &[String]) {
+        self.conn.execute("DELETE FROM course_tags WHERE course_id = ?", [course_id]).unwrap();
+        for tag_id in tag_ids {
+            self.conn
+                .execute("INSERT OR IGNORE INTO course_tags (course_id, tag_id) VALUES (?, ?)", params![course_id, tag_id])
+                .unwrap();
+        }
+    }
+
+    pub fn get_session_tags(&self, session_id: &str) -> Vec<String> {
+        let mut stmt = self.conn.prepare("SELECT tag_id FROM session_tags WHERE session_id = ?").unwrap();
+        stmt.query_map([session_id], |row| row.get(0))
             .unwrap()
-            .filter_map(|r: Result<(String, String, String, i64), _>| r.ok())
+            .filter_map(|r| r.ok())
             .collect()
-        };
-
-        let total: i64 = rows.iter().map(|r| r.3).sum();
-        let course_stats: Vec<CourseStat> = rows
-            .into_iter()
-            .filter(|r| r.3 > 0)
-            .map(|(id, name, subj, duration)| CourseStat {
-                course_id: id,
-                course_name: name,
-                subject: subj,
-                duration,
-                percent: if total > 0 { (duration as f64 / total as f64) * 100.0 } else { 0.0 },
-            })
-            .collect();
+    }
 
-        // 按日期统计
+    pub fn set_session_tags(&self, session_id: &str, tag_ids: &[String]) -> Result<(), String> {
+        self.conn
+            .execute("DELETE FROM session_tags WHERE session_id = ?", [session_id])
+            .map_err(|e| e.to_string())?;
+        for tag_id in tag_ids {
+            self.conn
+                .execute("INSERT OR IGNORE INTO session_tags (session_id, tag_id) VALUES (?, ?)", params![session_id, tag_id])
+                .map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
+    pub fn get_chapters(&self, course_id: &str) -> Vec<Chapter> {
+        let mut stmt = self.conn
+            .prepare(
+                "SELECT id, course_id, name, sort_order, estimated_seconds, completed, url_pattern
+                 FROM chapters WHERE course_id = ? ORDER BY sort_order",
+            )
+            .unwrap();
+
+        stmt.query_map([course_id], |row| {
+            Ok(Chapter {
+                id: row.get(0)?,
+                course_id: row.get(1)?,
+                name: row.get(2)?,
+                sort_order: row.get(3)?,
+                estimated_seconds: row.get(4)?,
+                completed: row.get(5)?,
+                url_pattern: row.get(6)?,
+            })
+        })
+        .unwrap()
+        .filter_map(|r| r.ok())
+        .collect()
+    }
+
+    pub fn add_chapter(&self, course_id: &str, name: &str, estimated_seconds: i64, url_pattern: &str) {
+        let id = Uuid::new_v4().to_string();
+        self.conn
+            .execute(
+                "INSERT INTO chapters (id, course_id, name, sort_order, estimated_seconds, completed, url_pattern)
+                 VALUES (?1, ?2, ?3, (SELECT COALESCE(MAX(sort_order), -1) + 1 FROM chapters WHERE course_id = ?2), ?4, 0, ?5)",
+                params![id, course_id, name, estimated_seconds, url_pattern],
+            )
+            .unwrap();
+    }
+
+    pub fn update_chapter(&self, id: &str, name: &str, estimated_seconds: i64, url_pattern: &str, completed: bool) {
+        self.conn
+            .execute(
+                "UPDATE chapters SET name = ?, estimated_seconds = ?, url_pattern = ?, completed = ? WHERE id = ?",
+                params![name, estimated_seconds, url_pattern, completed, id],
+            )
+            .unwrap();
+    }
+
+    pub fn set_chapter_completed(&self, id: &str, completed: bool) {
+        self.conn
+            .execute("UPDATE chapters SET completed = ? WHERE id = ?", params![completed, id])
+            .unwrap();
+    }
+
+    pub fn reorder_chapters(&self, course_id: &str, ids: &[String]) {
+        for (order, id) in ids.iter().enumerate() {
+            self.conn
+                .execute(
+                    "UPDATE chapters SET sort_order = ? WHERE id = ? AND course_id = ?",
+                    params![order as i64, id, course_id],
+                )
+                .unwrap();
+        }
+    }
+
+    pub fn delete_chapter(&self, id: &str) {
+        self.conn.execute("DELETE FROM chapters WHERE id = ?", [id]).unwrap();
+        self.conn.execute("UPDATE sessions SET chapter_id = NULL WHERE chapter_id = ?", [id]).unwrap();
+    }
+
+    // 课程完成度：有预计时长时按时长加权，否则按章节数
+    fn course_completion(chapters: &[Chapter]) -> f64 {
+        let estimated: i64 = chapters.iter().map(|c| c.estimated_seconds).sum();
+        if estimated > 0 {
+            let done: i64 = chapters.iter().filter(|c| c.completed).map(|c| c.estimated_seconds).sum();
+            done as f64 / estimated as f64 * 100.0
+        } else if !chapters.is_empty() {
+            chapters.iter().filter(|c| c.completed).count() as f64 / chapters.len() as f64 * 100.0
+        } else {
+            0.0
+        }
+    }
+
+    fn get_chapter_stats(&self, course_id: &str, start: &str, end: &str) -> Vec<ChapterStat> {
+        self.get_chapters(course_id)
+            .into_iter()
+            .map(|c| {
+                let duration: i64 = self.conn
+                    .query_row(
+                        "SELECT COALESCE(SUM(duration), 0) FROM sessions
+                         WHERE chapter_id = ?1 AND date BETWEEN ?2 AND ?3",
+                        params![&c.id, start, end],
+                        |row| row.get(0),
+                    )
+                    .unwrap_or(0);
+                ChapterStat {
+                    chapter_id: c.id,
+                    name: c.name,
+                    duration,
+                    estimated_seconds: c.estimated_seconds,
+                    completed: c.completed,
+                }
+            })
+            .collect()
+    }
+
+    pub fn get_daily_goal(&self) -> i64 {
+        self.conn
+            .query_row(
+                "SELECT value FROM settings WHERE key = 'daily_goal'",
+                [],
+                |row| row.get::<_, String>(0),
+            )
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(7200) // 默认 2 小时
+    }
+
+    pub fn set_daily_goal(&self, seconds: i64) {
+        self.conn
+            .execute(
+                "INSERT OR REPLACE INTO settings (key, value) VALUES ('daily_goal', ?)",
+                [seconds.to_string()],
+            )
+            .unwrap();
+
+        // 从今天起生效，同一天多次修改只保留最后一次
+        let today = chrono::Local::now().format("%Y-%m-%d").to_string();
+        self.conn
+            .execute(
+                "INSERT OR REPLACE INTO goal_history (effective_date, seconds) VALUES (?, ?)",
+                params![today, seconds],
+            )
+            .unwrap();
+    }
+
+    pub fn get_goal_history(&self) -> Vec<GoalSnapshot> {
+        let mut stmt = self.conn
+            .prepare("SELECT effective_date, seconds FROM goal_history ORDER BY effective_date")
+            .unwrap();
+
+        stmt.query_map([], |row| {
+            Ok(GoalSnapshot {
+                effective_date: row.get(0)?,
+                seconds: row.get(1)?,
+            })
+        })
+        .unwrap()
+        .filter_map(|r| r.ok())
+        .collect()
+    }
+
+    pub fn get_goals(&self) -> Vec<Goal> {
+        let mut stmt = self.conn
+            .prepare("SELECT id, scope, target, period, seconds FROM goals")
+            .unwrap();
+
+        stmt.query_map([], |row| {
+            Ok(Goal {
+                id: row.get(0)?,
+                scope: row.get(1)?,
+                target: row.get(2)?,
+                period: row.get(3)?,
+                seconds: row.get(4)?,
+            })
+        })
+        .unwrap()
+        .filter_map(|r| r.ok())
+        .collect()
+    }
+
+    pub fn add_goal(&self, scope: &str, target: &str, period: &str, seconds: i64) {
+        let id = Uuid::new_v4().to_string();
+        self.conn
+            .execute(
+                "INSERT INTO goals (id, scope, target, period, seconds) VALUES (?, ?, ?, ?, ?)",
+                params![id, scope, target, period, seconds],
+            )
+            .unwrap();
+    }
+
+    pub fn update_goal(&self, id: &str, scope: &str, target: &str, period: &str, seconds: i64) {
+        self.conn
+            .execute(
+                "UPDATE goals SET scope = ?, target = ?, period = ?, seconds = ? WHERE id = ?",
+                params![scope, target, period, seconds, id],
+            )
+            .unwrap();
+    }
+
+    pub fn delete_goal(&self, id: &str) {
+        self.conn.execute("DELETE FROM goals WHERE id = ?", [id]).unwrap();
+    }
+
+    // 各目标在 date 所在周期内的进度
+    pub fn get_goal_progress(&self, date: NaiveDate) -> Vec<GoalProgress> {
+        self.get_goals()
+            .into_iter()
+            .map(|g| {
+                let (start, end) = goals::period_range(&g.period, date);
+                let start = start.format("%Y-%m-%d").to_string();
+                let end = end.format("%Y-%m-%d").to_string();
+                let (label, studied) = if g.scope == "course" {
+                    let label = self.get_course(&g.target).map(|c| c.name).unwrap_or_default();
+                    let studied: i64 = self.conn
+                        .query_row(
+                            "SELECT COALESCE(SUM(duration), 0) FROM study_logs
+                             WHERE course_id = ?1 AND date BETWEEN ?2 AND ?3",
+                            params![&g.target, &start, &end],
+                            |row| row.get(0),
+                        )
+                        .unwrap_or(0);
+                    (label, studied)
+                } else {
+                    let studied: i64 = self.conn
+                        .query_row(
+                            &format!(
+                                "SELECT COALESCE(SUM(l.duration), 0) FROM study_logs l
+                                 JOIN courses c ON l.course_id = c.id
+                                 WHERE c.subject_id IN {} AND l.date BETWEEN ?2 AND ?3",
+                                subject_tree("(SELECT id FROM subjects WHERE name = ?1)")
+                            ),
+                            params![&g.target, &start, &end],
+                            |row| row.get(0),
+                        )
+                        .unwrap_or(0);
+                    (g.target.clone(), studied)
+                };
+                GoalProgress {
+                    goal_id: g.id,
+                    scope: g.scope,
+                    target: g.target,
+                    label,
+                    period: g.period,
+                    period_start: start,
+                    period_end: end,
+                    goal: g.seconds,
+                    studied,
+                    met: studied >= g.seconds,
+                }
+            })
+            .collect()
+    }
+
+    pub fn get_studied_duration(&self, date: &str) -> i64 {
+        self.conn
+            .query_row(
+                "SELECT COALESCE(SUM(duration), 0) FROM study_logs WHERE date = ?",
+                [date],
+                |row| row.get(0),
+            )
+            .unwrap_or(0)
+    }
+
+    // 课程不存在时不记录
+    pub fn add_study_log(&self, course_id: &str, date: &str, duration: i64) -> Result<(), String> {
+        self.get_course(course_id).ok_or("课程不存在")?;
+
+        // 尝试更新本机的现有记录，其它设备同步来的记录不修改
+        let device_id = self.device_id();
+        let updated = self.conn
+            .execute(
+                "UPDATE study_logs SET duration = duration + ? WHERE course_id = ? AND date = ? AND device_id = ?",
+                params![duration, course_id, date, device_id],
+            )
+            .map_err(|e| e.to_string())?;
+
+        // 如果没有更新到记录，插入新记录
+        if updated == 0 {
+            let id = Uuid::new_v4().to_string();
+            self.conn
+                .execute(
+                    "INSERT INTO study_logs (id, course_id, date, duration, device_id) VALUES (?, ?, ?, ?, ?)",
+                    params![id, course_id, date, duration, device_id],
+                )
+                .map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
+    // 记录一次完整会话，同时累加到当天的学习记录
+    // 返回新会话的 id，课程不存在时不记录
+    // 学习记录、会话和标签在一个事务中写入，不会只记下一部分
+    pub fn record_session(&self, course_id: &str, chapter_id: Option<&str>, tag_ids: &[String], date: &str, start: i64, end: i64) -> Result<String, String> {
+        let tx = self.conn.unchecked_transaction().map_err(|e| e.to_string())?;
+        let id = self.insert_session(course_id, chapter_id, tag_ids, date, start, end)?;
+        tx.commit().map_err(|e| e.to_string())?;
+        Ok(id)
+    }
+
+    // 同 record_session，由调用方开启事务
+    fn insert_session(&self, course_id: &str, chapter_id: Option<&str>, tag_ids: &[String], date: &str, start: i64, end: i64) -> Result<String, String> {
+        self.add_study_log(course_id, date, end - start)?;
+        let id = Uuid::new_v4().to_string();
+        self.conn
+            .execute(
+                "INSERT INTO sessions (id, course_id, chapter_id, date, start_time, end_time, duration, device_id) VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+                params![id, course_id, chapter_id, date, start, end, end - start, self.device_id()],
+            )
+            .map_err(|e| e.to_string())?;
+        self.set_session_tags(&id, tag_ids)?;
+        Ok(id)
+    }
+
+    pub fn get_sessions(&self, start_date: &str, end_date: &str) -> Vec<StudySession> {
+        let mut stmt = self.conn
+            .prepare(
+                "SELECT id, course_id, chapter_id, date, start_time, end_time, duration, note, focus, titles FROM sessions
+                 WHERE date BETWEEN ?1 AND ?2
+                 ORDER BY start_time",
+            )
+            .unwrap();
+
+        stmt.query_map(params![start_date, end_date], row_to_session)
+            .unwrap()
+            .filter_map(|r| r.ok())
+            .collect()
+    }
+
+    // 最近记录的一次会话
+    pub fn get_last_session_id(&self) -> Option<String> {
+        self.conn
+            .query_row("SELECT id FROM sessions ORDER BY end_time DESC LIMIT 1", [], |row| row.get(0))
+            .ok()
+    }
+
+    pub fn annotate_session(&self, id: &str, note: &str, focus: Option<i64>) -> Result<(), String> {
+        let updated = self.conn
+            .execute("UPDATE sessions SET note = ?, focus = ? WHERE id = ?", params![note, focus, id])
+            .map_err(|e| e.to_string())?;
+        if updated == 0 {
+            return Err("会话不存在".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn set_session_titles(&self, id: &str, titles: &[String]) {
+        self.conn
+            .execute("UPDATE sessions SET titles = ? WHERE id = ?", params![titles.join("\n"), id])
+            .unwrap();
+    }
+
+    // 全文搜索，FTS5 命中按相关度排序，短词回退到 LIKE 时按日期排序
+    pub fn search(&self, query: &str, limit: i64) -> Vec<SearchHit> {
+        let terms = search::terms(query);
+        if terms.is_empty() {
+            return Vec::new();
+        }
+        let rows: Vec<(String, String, String, String, f64)> = match search::match_expression(&terms) {
+            Some(expr) => {
+                let mut stmt = self.conn
+                    .prepare(
+                        "SELECT kind, ref_id, name, body, rank FROM search_index
+                         WHERE search_index MATCH ?1 ORDER BY rank LIMIT ?2",
+                    )
+                    .unwrap();
+                stmt.query_map(params![expr, limit], |row| {
+                    Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, -row.get::<_, f64>(4)?))
+                })
+                .unwrap()
+                .filter_map(|r| r.ok())
+                .collect()
+            }
+            None => {
+                let conditions = vec!["(name LIKE ? OR body LIKE ?)"; terms.len()].join(" AND ");
+                let patterns: Vec<String> = terms
+                    .iter()
+                    .flat_map(|t| {
+                        let p = format!("%{}%", t);
+                        [p.clone(), p]
+                    })
+                    .collect();
+                let mut stmt = self.conn
+                    .prepare(&format!(
+                        "SELECT kind, ref_id, name, body FROM search_index WHERE {} LIMIT {}",
+                        conditions, limit
+                    ))
+                    .unwrap();
+                stmt.query_map(rusqlite::params_from_iter(patterns), |row| {
+                    Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, 0.0))
+                })
+                .unwrap()
+                .filter_map(|r| r.ok())
+                .collect()
+            }
+        };
+
+        let mut hits: Vec<SearchHit> = rows
+            .into_iter()
+            .filter_map(|(kind, id, name, body, score)| self.search_hit(kind, id, &name, &body, &terms, score))
+            .collect();
+        if hits.iter().all(|h| h.score == 0.0) {
+            hits.sort_by(|a, b| b.date.cmp(&a.date));
+        }
+        hits
+    }
+
+    // 补全命中项对应的课程、日期和时长：课程和章节取最近学习日期与累计时长，会话取本次记录
+    fn search_hit(&self, kind: String, id: String, name: &str, body: &str, terms: &[String], score: f64) -> Option<SearchHit> {
+        let (course_id, title, text, date, duration): (String, String, String, Option<String>, i64) = match kind.as_str() {
+            "course" => self.conn
+                .query_row(
+                    "SELECT MAX(date), COALESCE(SUM(duration), 0) FROM study_logs WHERE course_id = ?",
+                    [&id],
+                    |row| Ok((id.clone(), name.to_string(), body.to_string(), row.get(0)?, row.get(1)?)),
+                )
+                .ok()?,
+            "chapter" => self.conn
+                .query_row(
+                    "SELECT ch.course_id, MAX(s.date), COALESCE(SUM(s.duration), 0)
+                     FROM chapters ch LEFT JOIN sessions s ON s.chapter_id = ch.id
+                     WHERE ch.id = ? GROUP BY ch.id",
+                    [&id],
+                    |row| Ok((row.get(0)?, name.to_string(), String::new(), row.get(1)?, row.get(2)?)),
+                )
+                .ok()?,
+            _ => self.conn
+                .query_row(
+                    "SELECT course_id, date, duration FROM sessions WHERE id = ?",
+                    [&id],
+                    |row| {
+                        let text = if name.is_empty() { body.to_string() } else { format!("{}\n{}", name, body) };
+                        Ok((row.get(0)?, String::new(), text, Some(row.get(1)?), row.get(2)?))
+                    },
+                )
+                .ok()?,
+        };
+        let course_name = self.get_course(&course_id).map(|c| c.name).unwrap_or_default();
+        Some(SearchHit {
+            kind,
+            id,
+            title: if title.is_empty() { course_name.clone() } else { title },
+            snippet: search::snippet(text.trim(), terms),
+            course_id,
+            course_name,
+            date,
+            duration,
+            score,
+        })
+    }
+
+    // 按备注内容搜索会话，最近的在前
+    pub fn search_session_notes(&self, query: &str) -> Vec<StudySession> {
+        let mut stmt = self.conn
+            .prepare(
+                "SELECT id, course_id, chapter_id, date, start_time, end_time, duration, note, focus, titles FROM sessions
+                 WHERE note != '' AND note LIKE '%' || ?1 || '%'
+                 ORDER BY start_time DESC",
+            )
+            .unwrap();
+
+        stmt.query_map([query], row_to_session)
+            .unwrap()
+            .filter_map(|r| r.ok())
+            .collect()
+    }
+
+    // 时段热力图：按本地时区的星期 × 小时汇总会话时长
+    pub fn get_time_heatmap(
+        &self,
+        start_date: Option<String>,
+        end_date: Option<String>,
+        subject_id: Option<String>,
+    ) -> TimeHeatmap {
+        let start = start_date.unwrap_or_else(|| "1970-01-01".to_string());
+        let end = end_date.unwrap_or_else(|| "2099-12-31".to_string());
+
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT c.subject, s.start_time, s.end_time
+             FROM sessions s
+             JOIN courses c ON s.course_id = c.id
+             WHERE s.date BETWEEN ?1 AND ?2 AND (?3 IS NULL OR c.subject_id IN {})",
+            subject_tree("?3")
+        )).unwrap();
+        let sessions: Vec<(String, i64, i64)> = stmt
+            .query_map(params![&start, &end, &subject_id], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })
+            .unwrap()
+            .filter_map(|r| r.ok())
+            .collect();
+
+        heatmap::bin_sessions(&sessions)
+    }
+
+    pub fn get_statistics(
+        &self,
+        start_date: Option<String>,
+        end_date: Option<String>,
+        subject_id: Option<String>,
+        tag: Option<String>,
+        granularity: Option<String>,
+    ) -> Statistics {
+        let explicit_range = (start_date.clone(), end_date.clone());
+        let start = start_date.unwrap_or_else(|| "1970-01-01".to_string());
+        let end = end_date.unwrap_or_else(|| "2099-12-31".to_string());
+
+        let tag = tag.filter(|t| !t.is_empty());
+
+        // 所有科目及范围内的时长，上级科目汇总下级科目
+        let own: HashMap<String, i64> = self.conn
+            .prepare(&format!(
+                "SELECT c.subject_id, SUM(l.duration)
+                 FROM {} l
+                 JOIN courses c ON l.course_id = c.id
+                 WHERE l.date BETWEEN ?1 AND ?2 AND c.subject_id IS NOT NULL
+                 GROUP BY c.subject_id",
+                TAGGED_LOGS
+            ))
+            .unwrap()
+            .query_map(params![&start, &end, &subject_id, &tag], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .filter_map(|r| r.ok())
+            .collect();
+        let subjects = aggregation::roll_up_subjects(&self.get_subjects(), &own);
+
+        // 各标签的时长，同样受科目筛选影响
+        let tags: Vec<TagStat> = self
+            .get_tags()
+            .into_iter()
+            .map(|t| {
+                let duration: i64 = self.conn
+                    .query_row(
+                        &format!(
+                            "SELECT COALESCE(SUM(l.duration), 0)
+                             FROM {} l
+                             JOIN courses c ON l.course_id = c.id
+                             WHERE l.date BETWEEN ?1 AND ?2 AND (?3 IS NULL OR c.subject_id IN {})",
+                            TAGGED_LOGS, subject_tree("?3")
+                        ),
+                        params![&start, &end, &subject_id, &t.name],
+                        |row| row.get(0),
+                    )
+                    .unwrap_or(0);
+                TagStat { id: t.id, name: t.name, duration }
+            })
+            .collect();
+
+        // 按课程统计
+        let rows: Vec<(String, String, String, i64)> = self.conn
+            .prepare(&format!(
+                "SELECT c.id, c.name, c.subject, COALESCE(SUM(l.duration), 0) as total
+                 FROM courses c
+                 LEFT JOIN {} l ON c.id = l.course_id AND l.date BETWEEN ?1 AND ?2
+                 WHERE ?3 IS NULL OR c.subject_id IN {}
+                 GROUP BY c.id",
+                TAGGED_LOGS, subject_tree("?3")
+            ))
+            .unwrap()
+            .query_map(params![&start, &end, &subject_id, &tag], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })
+            .unwrap()
+            .filter_map(|r| r.ok())
+            .collect();
+
+        let total: i64 = rows.iter().map(|r| r.3).sum();
+        let course_stats: Vec<CourseStat> = rows
+            .into_iter()
+            .filter(|r| r.3 > 0)
+            .map(|(id, name, subj, duration)| CourseStat {
+                completion: Self::course_completion(&self.get_chapters(&id)),
+                chapters: self.get_chapter_stats(&id, &start, &end),
+                course_id: id,
+                course_name: name,
+                subject: subj,
+                duration,
+                percent: if total > 0 { (duration as f64 / total as f64) * 100.0 } else { 0.0 },
+            })
+            .collect();
+
+        // 按日期统计，每天按当天生效的目标判断
+        let history = self.get_goal_history();
         let daily_goal = self.get_daily_goal();
-        let daily_stats: Vec<DailyStat> = if let Some(ref subj) = subject {
-            let mut stmt = self.conn.prepare(
+        let goal_for = |date: &str| goal_on(&history, date).unwrap_or(daily_goal);
+        let daily_stats: Vec<DailyStat> = self.conn
+            .prepare(&format!(
                 "SELECT l.date, SUM(l.duration) as total
-                 FROM study_logs l
+                 FROM {} l
                  JOIN courses c ON l.course_id = c.id
-                 WHERE l.date BETWEEN ?1 AND ?2 AND c.subject = ?3
+                 WHERE l.date BETWEEN ?1 AND ?2 AND (?3 IS NULL OR c.subject_id IN {})
                  GROUP BY l.date
-                 ORDER BY l.date DESC"
-            ).unwrap();
-            stmt.query_map(params![&start, &end, subj], |row| {
+                 ORDER BY l.date DESC",
+                TAGGED_LOGS, subject_tree("?3")
+            ))
+            .unwrap()
+            .query_map(params![&start, &end, &subject_id, &tag], |row| {
+                let date: String = row.get(0)?;
                 let duration: i64 = row.get(1)?;
+                let goal = goal_for(&date);
                 Ok(DailyStat {
-                    date: row.get(0)?,
+                    date,
                     duration,
-                    goal_met: duration >= daily_goal,
+                    goal,
+                    goal_met: duration >= goal,
                 })
             })
             .unwrap()
-            .filter_map(|r: Result<DailyStat, _>| r.ok())
+            .filter_map(|r| r.ok())
+            .collect();
+
+        // 按日 / 周 / 月 / 年汇总，附带各区间的科目分布
+        let buckets = match granularity {
+            Some(ref g) if aggregation::is_valid_granularity(g) => {
+                let rows = self.get_subject_durations(&start, &end, subject_id.as_deref(), tag.as_deref());
+                // 未指定范围时以有记录的首尾日期为界
+                let first = explicit_range.0.or_else(|| rows.iter().map(|r| r.0.clone()).min());
+                let last = explicit_range.1.or_else(|| rows.iter().map(|r| r.0.clone()).max());
+                let parse = |d: Option<String>| d.and_then(|d| NaiveDate::parse_from_str(&d, "%Y-%m-%d").ok());
+                match (parse(first), parse(last)) {
+                    (Some(first), Some(last)) => aggregation::build_buckets(&rows, g, first, last),
+                    _ => Vec::new(),
+                }
+            }
+            _ => Vec::new(),
+        };
+
+        // 目标进度：以结束日期（不晚于今天）所在的周期计算
+        let today = chrono::Local::now().date_naive();
+        let reference = NaiveDate::parse_from_str(&end, "%Y-%m-%d")
+            .map(|d| d.min(today))
+            .unwrap_or(today);
+        let tree = subject_id.as_deref().map(|id| self.subject_tree_ids(id));
+        let all_subjects = self.get_subjects();
+        let goal_progress = self
+            .get_goal_progress(reference)
+            .into_iter()
+            .filter(|p| match (&tree, p.scope.as_str()) {
+                (None, _) => true,
+                (Some(ids), "course") => self.get_course(&p.target).map(|c| ids.contains(&c.subject_id)).unwrap_or(false),
+                (Some(ids), _) => all_subjects.iter().any(|s| s.name == p.target && ids.contains(&s.id)),
+            })
+            .collect();
+
+        Statistics {
+            subjects,
+            tags,
+            course_stats,
+            daily_stats,
+            buckets,
+            goal_progress,
+        }
+    }
+
+    // 学习报告：period 为 weekly 或 monthly，统计 date 所在的周期并与上一周期对比
+    // 达标天数、连续天数和目标进度都截止到周期结束日（不晚于今天）
+    pub fn get_report(&self, period: &str, date: NaiveDate) -> Result<Report, String> {
+        if !report::is_valid_period(period) {
+            return Err("无效的报告周期".to_string());
+        }
+        let today = chrono::Local::now().date_naive();
+        let (start, end) = goals::period_range(period, date);
+        let (previous_start, previous_end) = goals::period_range(period, start - chrono::Duration::days(1));
+        let format = |d: NaiveDate| d.format("%Y-%m-%d").to_string();
+
+        let current = self.get_statistics(Some(format(start)), Some(format(end)), None, None, None);
+        let previous = self.get_statistics(Some(format(previous_start)), Some(format(previous_end)), None, None, None);
+
+        // 顶级科目，含下级科目的时长
+        let subjects: Vec<ReportSubject> = current
+            .subjects
+            .iter()
+            .filter(|s| s.parent_id.is_none())
+            .map(|s| ReportSubject {
+                name: s.name.clone(),
+                color: s.color.clone(),
+                duration: s.total_duration,
+                previous_duration: previous
+                    .subjects
+                    .iter()
+                    .find(|p| p.id == s.id)
+                    .map(|p| p.total_duration)
+                    .unwrap_or(0),
+            })
+            .filter(|s| s.duration > 0 || s.previous_duration > 0)
+            .collect();
+
+        let reference = end.min(today);
+        let days = (reference - start).num_days() + 1;
+        Ok(Report {
+            period: period.to_string(),
+            start_date: format(start),
+            end_date: format(end),
+            previous_start: format(previous_start),
+            previous_end: format(previous_end),
+            total: current.course_stats.iter().map(|c| c.duration).sum(),
+            previous_total: previous.course_stats.iter().map(|c| c.duration).sum(),
+            days: days.max(0),
+            studied_days: current.daily_stats.iter().filter(|d| d.duration > 0).count() as i64,
+            goal_met_days: current.daily_stats.iter().filter(|d| d.goal_met).count() as i64,
+            subjects,
+            course_stats: current.course_stats,
+            goal_progress: current.goal_progress,
+            streaks: self.get_streaks(reference),
+            exams: self.get_exam_countdowns(today),
+        })
+    }
+
+    // 导出 CSV，日期范围、科目和标签筛选与 get_statistics 一致
+    pub fn export_csv(
+        &self,
+        kind: &str,
+        start_date: Option<String>,
+        end_date: Option<String>,
+        subject_id: Option<String>,
+        tag: Option<String>,
+    ) -> Result<String, String> {
+        let start = start_date.unwrap_or_else(|| "1970-01-01".to_string());
+        let end = end_date.unwrap_or_else(|| "2099-12-31".to_string());
+        let subject_id = subject_id.filter(|s| !s.is_empty());
+        let tag = tag.filter(|t| !t.is_empty());
+        match kind {
+            "logs" => export::logs_csv(&self.get_export_logs(&start, &end, subject_id.as_deref(), tag.as_deref())),
+            "sessions" => export::sessions_csv(&self.get_export_sessions(&start, &end, subject_id.as_deref(), tag.as_deref())),
+            _ => Err("无效的导出类型".to_string()),
+        }
+    }
+
+    // iCalendar 日历：范围内的学习会话、全部考试和学习计划
+    // 每周重复的计划从范围开始（未指定时为今天）起重复
+    pub fn export_ics(&self, start_date: Option<String>, end_date: Option<String>) -> String {
+        let today = chrono::Local::now().date_naive();
+        let from = start_date
+            .as_deref()
+            .and_then(|d| NaiveDate::parse_from_str(d, "%Y-%m-%d").ok())
+            .unwrap_or(today);
+        let start = start_date.unwrap_or_else(|| "1970-01-01".to_string());
+        let end = end_date.unwrap_or_else(|| "2099-12-31".to_string());
+        let sessions = self.get_export_sessions(&start, &end, None, None);
+
+        let exams = self.get_exams();
+
+        let courses = self.get_courses();
+        let blocks: Vec<(PlannedBlock, String)> = self
+            .get_planned_blocks()
+            .into_iter()
+            .filter(|b| b.date.as_deref().is_none_or(|d| d >= start.as_str() && d <= end.as_str()))
+            .filter_map(|b| {
+                let name = courses.iter().find(|c| c.id == b.course_id)?.name.clone();
+                Some((b, name))
+            })
+            .collect();
+
+        ics::calendar(&sessions, &exams, &blocks, from)
+    }
+
+    fn get_export_logs(&self, start: &str, end: &str, subject_id: Option<&str>, tag: Option<&str>) -> Vec<ExportLog> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT l.date, c.id, c.name, c.subject, SUM(l.duration)
+             FROM {} l
+             JOIN courses c ON l.course_id = c.id
+             WHERE l.date BETWEEN ?1 AND ?2 AND (?3 IS NULL OR c.subject_id IN {})
+             GROUP BY l.date, c.id
+             ORDER BY l.date, c.name",
+            TAGGED_LOGS, subject_tree("?3")
+        )).unwrap();
+        stmt.query_map(params![start, end, subject_id, tag], |row| {
+            Ok(ExportLog {
+                date: row.get(0)?,
+                course_id: row.get(1)?,
+                course_name: row.get(2)?,
+                subject: row.get(3)?,
+                duration: row.get(4)?,
+            })
+        })
+        .unwrap()
+        .filter_map(|r| r.ok())
+        .collect()
+    }
+
+    // 会话的标签包括会话本身的标签和所属课程的标签
+    fn get_export_sessions(&self, start: &str, end: &str, subject_id: Option<&str>, tag: Option<&str>) -> Vec<ExportSession> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT s.id, s.date, s.start_time, s.end_time, c.id, c.name, c.subject, ch.name, s.duration, s.focus, s.note
+             FROM sessions s
+             JOIN courses c ON s.course_id = c.id
+             LEFT JOIN chapters ch ON s.chapter_id = ch.id
+             WHERE s.date BETWEEN ?1 AND ?2 AND (?3 IS NULL OR c.subject_id IN {})
+               AND (?4 IS NULL
+                    OR s.id IN (SELECT st.session_id FROM session_tags st JOIN tags t ON st.tag_id = t.id WHERE t.name = ?4)
+                    OR c.id IN (SELECT ct.course_id FROM course_tags ct JOIN tags t ON ct.tag_id = t.id WHERE t.name = ?4))
+             ORDER BY s.start_time",
+            subject_tree("?3")
+        )).unwrap();
+        let rows: Vec<ExportSession> = stmt.query_map(params![start, end, subject_id, tag], |row| {
+            Ok(ExportSession {
+                id: row.get(0)?,
+                date: row.get(1)?,
+                start_time: row.get(2)?,
+                end_time: row.get(3)?,
+                course_id: row.get(4)?,
+                course_name: row.get(5)?,
+                subject: row.get(6)?,
+                chapter_name: row.get(7)?,
+                duration: row.get(8)?,
+                focus: row.get(9)?,
+                note: row.get(10)?,
+                tags: Vec::new(),
+            })
+        })
+        .unwrap()
+        .filter_map(|r| r.ok())
+        .collect();
+
+        let mut tag_stmt = self.conn.prepare(
+            "SELECT name FROM tags
+             WHERE id IN (SELECT tag_id FROM session_tags WHERE session_id = ?1)
+                OR id IN (SELECT tag_id FROM course_tags WHERE course_id = ?2)
+             ORDER BY name"
+        ).unwrap();
+        rows.into_iter()
+            .map(|mut row| {
+                row.tags = tag_stmt
+                    .query_map(params![row.id, row.course_id], |r| r.get(0))
+                    .unwrap()
+                    .filter_map(|r| r.ok())
+                    .collect();
+                row
+            })
             .collect()
-        } else {
-            let mut stmt = self.conn.prepare(
-                "SELECT date, SUM(duration) as total
-                 FROM study_logs
-                 WHERE date BETWEEN ?1 AND ?2
-                 GROUP BY date
-                 ORDER BY date DESC"
-            ).unwrap();
-            stmt.query_map(params![&start, &end], |row| {
-                let duration: i64 = row.get(1)?;
-                Ok(DailyStat {
-                    date: row.get(0)?,
-                    duration,
-                    goal_met: duration >= daily_goal,
-                })
+    }
+
+    // 导入时的课程匹配：先按指定的映射，再按课程名称（不区分大小写）
+    fn import_course(&self, project: &str, course_map: &HashMap<String, String>, courses: &[Course]) -> Option<String> {
+        course_map
+            .get(project)
+            .filter(|id| courses.iter().any(|c| &c.id == *id))
+            .cloned()
+            .or_else(|| courses.iter().find(|c| c.name.to_lowercase() == project.to_lowercase()).map(|c| c.id.clone()))
+    }
+
+    fn is_imported(&self, key: &str) -> bool {
+        self.conn
+            .query_row("SELECT 1 FROM import_entries WHERE key = ?", [key], |_| Ok(()))
+            .is_ok()
+    }
+
+    // 已导入过或在同一文件中重复出现的记录标为重复
+    pub fn preview_import(&self, rows: Vec<ImportRow>, errors: Vec<String>, course_map: &HashMap<String, String>) -> ImportPreview {
+        let courses = self.get_courses();
+        let mut seen = std::collections::HashSet::new();
+        let mut new_courses: Vec<String> = Vec::new();
+        let rows: Vec<ImportRow> = rows
+            .into_iter()
+            .map(|mut row| {
+                let key = import::entry_key(&row);
+                row.duplicate = self.is_imported(&key) || !seen.insert(key);
+                row.course_id = self.import_course(&row.project, course_map, &courses);
+                match &row.course_id {
+                    Some(course_id) => {
+                        row.existing = self.conn
+                            .query_row(
+                                "SELECT COALESCE(SUM(duration), 0) FROM study_logs WHERE course_id = ? AND date = ?",
+                                params![course_id, row.date],
+                                |r| r.get(0),
+                            )
+                            .unwrap_or(0);
+                    }
+                    None if !new_courses.iter().any(|n| n.to_lowercase() == row.project.to_lowercase()) => {
+                        new_courses.push(row.project.clone());
+                    }
+                    None => {}
+                }
+                row
             })
+            .collect();
+
+        ImportPreview {
+            duplicates: rows.iter().filter(|r| r.duplicate).count(),
+            duration: rows.iter().filter(|r| !r.duplicate).map(|r| r.duration).sum(),
+            rows,
+            errors,
+            new_courses,
+        }
+    }
+
+    // 在一个事务中写入：有开始时间的记录为会话（描述作为备注），否则只计入每日记录
+    pub fn commit_import(&self, rows: Vec<ImportRow>, course_map: &HashMap<String, String>, skip_duplicates: bool) -> Result<ImportResult, String> {
+        let preview = self.preview_import(rows, Vec::new(), course_map);
+        let tx = self.conn.unchecked_transaction().map_err(|e| e.to_string())?;
+        let now = chrono::Utc::now().timestamp();
+        let mut result = ImportResult { imported: 0, skipped: 0, created_courses: Vec::new(), duration: 0 };
+        let mut created: HashMap<String, String> = HashMap::new();
+
+        for row in preview.rows {
+            if row.duplicate && skip_duplicates {
+                result.skipped += 1;
+                continue;
+            }
+            let course_id = match row.course_id.clone() {
+                Some(id) => id,
+                None => match created.get(&row.project.to_lowercase()) {
+                    Some(id) => id.clone(),
+                    None => {
+                        let subject = if row.subject.is_empty() { "导入" } else { row.subject.as_str() };
+                        let subject_id = self.ensure_subject(subject);
+                        let id = self.add_course(&row.project, &subject_id, "", "", "")?;
+                        created.insert(row.project.to_lowercase(), id.clone());
+                        result.created_courses.push(row.project.clone());
+                        id
+                    }
+                },
+            };
+
+            match row.start_time {
+                Some(start) => {
+                    let session_id = self.insert_session(&course_id, None, &[], &row.date, start, start + row.duration)?;
+                    if !row.description.is_empty() {
+                        self.annotate_session(&session_id, &row.description, None)?;
+                    }
+                }
+                None => self.add_study_log(&course_id, &row.date, row.duration)?,
+            }
+            self.conn
+                .execute(
+                    "INSERT OR IGNORE INTO import_entries (key, imported_at) VALUES (?, ?)",
+                    params![import::entry_key(&row), now],
+                )
+                .map_err(|e| e.to_string())?;
+            result.imported += 1;
+            result.duration += row.duration;
+        }
+
+        tx.commit().map_err(|e| e.to_string())?;
+        Ok(result)
+    }
+
+    fn backup_tables(&self) -> Vec<String> {
+        let mut stmt = self.conn
+            .prepare("SELECT name FROM sqlite_master WHERE type = 'table' ORDER BY name")
+            .unwrap();
+        stmt.query_map([], |row| row.get::<_, String>(0))
             .unwrap()
-            .filter_map(|r: Result<DailyStat, _>| r.ok())
+            .filter_map(|r| r.ok())
+            .filter(|name| backup::is_backup_table(name))
             .collect()
-        };
+    }
+
+    fn table_columns(&self, table: &str) -> Vec<String> {
+        let mut stmt = self.conn.prepare(&format!("PRAGMA table_info({})", table)).unwrap();
+        stmt.query_map([], |row| row.get(1))
+            .unwrap()
+            .filter_map(|r| r.ok())
+            .collect()
+    }
 
-        Statistics {
-            subjects,
-            course_stats,
-            daily_stats,
+    // 完整备份：所有表的所有行，按列名保存，新增的表和列会自动包含在内
+    pub fn export_backup(&self) -> serde_json::Value {
+        let mut tables = serde_json::Map::new();
+        for table in self.backup_tables() {
+            let mut stmt = self.conn.prepare(&format!("SELECT * FROM {}", table)).unwrap();
+            let columns: Vec<String> = stmt.column_names().iter().map(|c| c.to_string()).collect();
+            let rows: Vec<serde_json::Value> = stmt
+                .query_map([], |row| {
+                    let mut object = serde_json::Map::new();
+                    for (i, column) in columns.iter().enumerate() {
+                        object.insert(column.clone(), backup::to_json(row.get_ref(i)?));
+                    }
+                    Ok(serde_json::Value::Object(object))
+                })
+                .unwrap()
+                .filter_map(|r| r.ok())
+                // 本机的设备 id、同步密钥和密码不写入备份，恢复时也保留本机的值
+                .filter(|row| table != "settings" || !row["key"].as_str().is_some_and(|k| LOCAL_SETTINGS.contains(&k)))
+                .collect();
+            tables.insert(table, rows.into());
+        }
+        serde_json::json!({
+            "format": backup::FORMAT,
+            "version": backup::VERSION,
+            "schema_version": SCHEMA_VERSION,
+            "created_at": chrono::Local::now().to_rfc3339(),
+            "tables": tables,
+        })
+    }
+
+    // 恢复备份：replace 清空后按备份写入；merge 只补充本地没有的行（按主键）
+    // 旧版本的备份缺少的列使用默认值，本地没有的表和列忽略；返回写入的行数
+    pub fn import_backup(&self, data: &serde_json::Value, mode: &str) -> Result<usize, String> {
+        if data["format"] != backup::FORMAT {
+            return Err("不是有效的备份文件".to_string());
+        }
+        if data["version"].as_i64().unwrap_or(i64::MAX) > backup::VERSION
+            || data["schema_version"].as_i64().unwrap_or(i64::MAX) > SCHEMA_VERSION
+        {
+            return Err("备份来自更新版本的应用，请先升级".to_string());
+        }
+        let tables = data["tables"].as_object().ok_or("备份文件缺少数据")?;
+        let known = self.backup_tables();
+
+        // 备份可能来自其它设备，恢复后仍使用本机的设备 id 和同步密钥
+        self.device_id();
+        let local: Vec<(&str, Option<String>)> = LOCAL_SETTINGS.iter().map(|k| (*k, self.get_setting(k))).collect();
+        let tx = self.conn.unchecked_transaction().map_err(|e| e.to_string())?;
+        // 外键在提交时统一检查，写入顺序不受表之间的引用影响
+        self.conn.execute_batch("PRAGMA defer_foreign_keys = ON").map_err(|e| e.to_string())?;
+        if mode == "replace" {
+            for table in &known {
+                self.conn.execute(&format!("DELETE FROM {}", table), []).map_err(|e| e.to_string())?;
+            }
+        }
+        let verb = if mode == "replace" { "INSERT OR REPLACE" } else { "INSERT OR IGNORE" };
+
+        let mut written = 0;
+        for (table, rows) in tables.iter().filter(|(t, _)| known.contains(t)) {
+            let columns = self.table_columns(table);
+            for row in rows.as_array().into_iter().flatten().filter_map(|r| r.as_object()) {
+                let present: Vec<&String> = columns.iter().filter(|c| row.contains_key(*c)).collect();
+                if present.is_empty() {
+                    continue;
+                }
+                let sql = format!(
+                    "{} INTO {} ({}) VALUES ({})",
+                    verb,
+                    table,
+                    present.iter().map(|c| c.as_str()).collect::<Vec<_>>().join(", "),
+                    vec!["?"; present.len()].join(", ")
+                );
+                let values = present.iter().map(|c| backup::from_json(&row[*c]));
+                written += self.conn
+                    .execute(&sql, rusqlite::params_from_iter(values))
+                    .map_err(|e| format!("恢复 {} 失败：{}", table, e))?;
+            }
         }
+
+        self.conn
+            .execute_batch(&format!("DELETE FROM search_index; {}", SEARCH_INDEX_FILL))
+            .map_err(|e| e.to_string())?;
+        for (key, value) in local {
+            match value {
+                Some(value) => self.set_setting(key, &value),
+                None => {
+                    self.conn
+                        .execute("DELETE FROM settings WHERE key = ?", [key])
+                        .map_err(|e| e.to_string())?;
+                }
+            }
+        }
+        tx.commit().map_err(|e| format!("备份数据不完整：{}", e))?;
+        Ok(written)
+    }
+
+    // 每天各科目的学习时长：(日期, 科目, 时长)
+    fn get_subject_durations(&self, start: &str, end: &str, subject_id: Option<&str>, tag: Option<&str>) -> Vec<(String, String, i64)> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT l.date, c.subject, SUM(l.duration)
+             FROM {} l
+             JOIN courses c ON l.course_id = c.id
+             WHERE l.date BETWEEN ?1 AND ?2 AND (?3 IS NULL OR c.subject_id IN {})
+             GROUP BY l.date, c.subject",
+            TAGGED_LOGS, subject_tree("?3")
+        )).unwrap();
+        stmt.query_map(params![start, end, subject_id, tag], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+        })
+        .unwrap()
+        .filter_map(|r| r.ok())
+        .collect()
+    }
+
+    pub fn get_streaks(&self, today: NaiveDate) -> Streaks {
+        let end = today.format("%Y-%m-%d").to_string();
+        let daily = self.get_statistics(None, Some(end), None, None, None).daily_stats;
+        let rest_days = self.get_setting("rest_days")
+            .map(|v| streaks::parse_rest_days(&v))
+            .unwrap_or_default();
+        streaks::compute_streaks(&daily, &rest_days, today, self.get_daily_goal())
     }
 
+    // 最近一场未结束的考试日期
     pub fn get_exam_date(&self) -> Option<String> {
+        let today = chrono::Local::now().format("%Y-%m-%d").to_string();
         self.conn
             .query_row(
-                "SELECT value FROM settings WHERE key = 'exam_date'",
-                [],
+                "SELECT date FROM exams WHERE date >= ? ORDER BY date LIMIT 1",
+                [&today],
                 |row| row.get::<_, String>(0),
             )
             .ok()
     }
 
+    // 兼容旧的单一考试日期：写入设置并同步到默认考试
     pub fn set_exam_date(&self, date: &str) {
         self.conn
             .execute(
@@ -300,6 +1818,312 @@ impl Database {
                 [date],
             )
             .unwrap();
+        self.set_default_exam_date(date);
+    }
+
+    fn set_default_exam_date(&self, date: &str) {
+        let updated = self.conn
+            .execute("UPDATE exams SET date = ? WHERE id = 'default'", [date])
+            .unwrap();
+        if updated == 0 {
+            self.conn
+                .execute(
+                    "INSERT INTO exams (id, name, date, target_seconds) VALUES ('default', '考试', ?, 0)",
+                    [date],
+                )
+                .unwrap();
+        }
+    }
+
+    pub fn get_exams(&self) -> Vec<Exam> {
+        let mut stmt = self.conn
+            .prepare("SELECT id, name, date, target_seconds FROM exams ORDER BY date")
+            .unwrap();
+        let exams: Vec<Exam> = stmt
+            .query_map([], |row| {
+                Ok(Exam {
+                    id: row.get(0)?,
+                    name: row.get(1)?,
+                    date: row.get(2)?,
+                    subjects: Vec::new(),
+                    course_ids: Vec::new(),
+                    target_seconds: row.get(3)?,
+                })
+            })
+            .unwrap()
+            .filter_map(|r| r.ok())
+            .collect();
+
+        let mut stmt = self.conn
+            .prepare("SELECT scope, target FROM exam_targets WHERE exam_id = ?")
+            .unwrap();
+        exams
+            .into_iter()
+            .map(|mut exam| {
+                let links: Vec<(String, String)> = stmt
+                    .query_map([&exam.id], |row| Ok((row.get(0)?, row.get(1)?)))
+                    .unwrap()
+                    .filter_map(|r| r.ok())
+                    .collect();
+                for (scope, target) in links {
+                    if scope == "course" {
+                        exam.course_ids.push(target);
+                    } else {
+                        exam.subjects.push(target);
+                    }
+                }
+                exam
+            })
+            .collect()
+    }
+
+    pub fn add_exam(&self, name: &str, date: &str, subjects: &[String], course_ids: &[String], target_seconds: i64) {
+        let id = Uuid::new_v4().to_string();
+        self.conn
+            .execute(
+                "INSERT INTO exams (id, name, date, target_seconds) VALUES (?, ?, ?, ?)",
+                params![id, name, date, target_seconds],
+            )
+            .unwrap();
+        self.set_exam_targets(&id, subjects, course_ids);
+    }
+
+    pub fn update_exam(&self, id: &str, name: &str, date: &str, subjects: &[String], course_ids: &[String], target_seconds: i64) {
+        self.conn
+            .execute(
+                "UPDATE exams SET name = ?, date = ?, target_seconds = ? WHERE id = ?",
+                params![name, date, target_seconds, id],
+            )
+            .unwrap();
+        self.set_exam_targets(id, subjects, course_ids);
+    }
+
+    pub fn delete_exam(&self, id: &str) {
+        self.conn.execute("DELETE FROM exams WHERE id = ?", [id]).unwrap();
+        self.conn.execute("DELETE FROM exam_targets WHERE exam_id = ?", [id]).unwrap();
+        // 默认考试来自旧的 exam_date 设置，一并删除，不再被迁移或同步恢复
+        if id == "default" {
+            self.conn.execute("DELETE FROM settings WHERE key = 'exam_date'", []).unwrap();
+        }
+    }
+
+    fn set_exam_targets(&self, exam_id: &str, subjects: &[String], course_ids: &[String]) {
+        self.conn.execute("DELETE FROM exam_targets WHERE exam_id = ?", [exam_id]).unwrap();
+        let links = subjects.iter().map(|s| ("subject", s)).chain(course_ids.iter().map(|c| ("course", c)));
+        for (scope, target) in links {
+            self.conn
+                .execute(
+                    "INSERT OR IGNORE INTO exam_targets (exam_id, scope, target) VALUES (?, ?, ?)",
+                    params![exam_id, scope, target],
+                )
+                .unwrap();
+        }
+    }
+
+    // 考试涉及的课程：关联的科目和课程，都没有关联时视为全部课程
+    fn exam_courses<'a>(exam: &Exam, courses: &'a [Course]) -> Vec<&'a Course> {
+        courses
+            .iter()
+            .filter(|c| {
+                (exam.subjects.is_empty() && exam.course_ids.is_empty())
+                    || exam.subjects.contains(&c.subject)
+                    || exam.course_ids.contains(&c.id)
+            })
+            .collect()
+    }
+
+    // 每个科目最近一场未结束的考试
+    pub fn get_exam_countdowns(&self, today: NaiveDate) -> Vec<ExamCountdown> {
+        let today_str = today.format("%Y-%m-%d").to_string();
+        let courses = self.get_courses();
+        let exams: Vec<Exam> = self.get_exams()
+            .into_iter()
+            .filter(|e| e.date >= today_str)
+            .collect();
+
+        let mut subjects: Vec<String> = courses.iter().map(|c| c.subject.clone()).collect();
+        subjects.sort();
+        subjects.dedup();
+
+        subjects
+            .into_iter()
+            .filter_map(|subject| {
+                // exams 已按日期排序，第一场覆盖该科目的就是最近的
+                let exam = exams.iter().find(|e| {
+                    Self::exam_courses(e, &courses).iter().any(|c| c.subject == subject)
+                })?;
+                let date = NaiveDate::parse_from_str(&exam.date, "%Y-%m-%d").ok()?;
+                Some(ExamCountdown {
+                    subject,
+                    exam_id: exam.id.clone(),
+                    exam_name: exam.name.clone(),
+                    date: exam.date.clone(),
+                    days_left: (date - today).num_days(),
+                })
+            })
+            .collect()
+    }
+
+    pub fn get_planned_blocks(&self) -> Vec<PlannedBlock> {
+        let mut stmt = self.conn
+            .prepare("SELECT id, course_id, weekday, date, start_time, end_time FROM planned_blocks ORDER BY start_time")
+            .unwrap();
+
+        stmt.query_map([], |row| {
+            Ok(PlannedBlock {
+                id: row.get(0)?,
+                course_id: row.get(1)?,
+                weekday: row.get(2)?,
+                date: row.get(3)?,
+                start_time: row.get(4)?,
+                end_time: row.get(5)?,
+            })
+        })
+        .unwrap()
+        .filter_map(|r| r.ok())
+        .collect()
+    }
+
+    pub fn add_planned_block(&self, course_id: &str, weekday: Option<u32>, date: Option<&str>, start_time: &str, end_time: &str) {
+        let id = Uuid::new_v4().to_string();
+        self.conn
+            .execute(
+                "INSERT INTO planned_blocks (id, course_id, weekday, date, start_time, end_time) VALUES (?, ?, ?, ?, ?, ?)",
+                params![id, course_id, weekday, date, start_time, end_time],
+            )
+            .unwrap();
+    }
+
+    pub fn update_planned_block(&self, id: &str, course_id: &str, weekday: Option<u32>, date: Option<&str>, start_time: &str, end_time: &str) {
+        self.conn
+            .execute(
+                "UPDATE planned_blocks SET course_id = ?, weekday = ?, date = ?, start_time = ?, end_time = ? WHERE id = ?",
+                params![course_id, weekday, date, start_time, end_time, id],
+            )
+            .unwrap();
+    }
+
+    pub fn delete_planned_block(&self, id: &str) {
+        self.conn.execute("DELETE FROM planned_blocks WHERE id = ?", [id]).unwrap();
+    }
+
+    // 计划与实际对比：每次计划标记为 hit / partial / missed（未结束的为 upcoming）
+    pub fn get_adherence_report(&self, start: NaiveDate, end: NaiveDate, now: i64) -> AdherenceReport {
+        // 会话按结束时的日期记录，前后各多取一天以覆盖跨午夜的会话
+        let sessions = self.get_sessions(
+            &(start - chrono::Duration::days(1)).format("%Y-%m-%d").to_string(),
+            &(end + chrono::Duration::days(1)).format("%Y-%m-%d").to_string(),
+        );
+        let courses = self.get_courses();
+        let course_name = |id: &str| {
+            courses.iter().find(|c| c.id == id).map(|c| c.name.clone()).unwrap_or_default()
+        };
+        let items = schedule::adherence(&self.get_planned_blocks(), &sessions, course_name, start, end, now);
+        let count = |status: &str| items.iter().filter(|i| i.status == status).count() as i64;
+
+        AdherenceReport {
+            hit: count("hit"),
+            partial: count("partial"),
+            missed: count("missed"),
+            items,
+        }
+    }
+
+    pub fn get_subject_targets(&self) -> Vec<(String, i64)> {
+        let mut stmt = self.conn
+            .prepare("SELECT subject, seconds FROM subject_targets ORDER BY subject")
+            .unwrap();
+        stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .filter_map(|r| r.ok())
+            .collect()
+    }
+
+    pub fn set_subject_target(&self, subject: &str, seconds: i64) {
+        if seconds > 0 {
+            self.conn
+                .execute(
+                    "INSERT OR REPLACE INTO subject_targets (subject, seconds) VALUES (?, ?)",
+                    params![subject, seconds],
+                )
+                .unwrap();
+        } else {
+            self.conn.execute("DELETE FROM subject_targets WHERE subject = ?", [subject]).unwrap();
+        }
+    }
+
+    fn get_course_studied(&self, course_id: &str, start: &str, end: &str) -> i64 {
+        self.conn
+            .query_row(
+                "SELECT COALESCE(SUM(duration), 0) FROM study_logs
+                 WHERE course_id = ?1 AND date BETWEEN ?2 AND ?3",
+                params![course_id, start, end],
+                |row| row.get(0),
+            )
+            .unwrap_or(0)
+    }
+
+    fn get_subject_studied(&self, subject: &str, start: &str, end: &str) -> i64 {
+        self.conn
+            .query_row(
+                &format!(
+                    "SELECT COALESCE(SUM(l.duration), 0) FROM study_logs l
+                     JOIN courses c ON l.course_id = c.id
+                     WHERE c.subject_id IN {} AND l.date BETWEEN ?2 AND ?3",
+                    subject_tree("(SELECT id FROM subjects WHERE name = ?1)")
+                ),
+                params![subject, start, end],
+                |row| row.get(0),
+            )
+            .unwrap_or(0)
+    }
+
+    // 考前规划：各科目剩余时长、所需每日进度和按最近速度推算的完成日期
+    // 各科目按最近一场考试计算；设置了目标时长的考试单独计算，exams 中 subject 为考试名称
+    pub fn get_exam_plan(&self, today: NaiveDate) -> ExamPlan {
+        let parse = |d: &str| NaiveDate::parse_from_str(d, "%Y-%m-%d").ok();
+        let exam_date = self.get_exam_date().and_then(|d| parse(&d));
+        let countdowns = self.get_exam_countdowns(today);
+        let today_str = today.format("%Y-%m-%d").to_string();
+        let window_start = (today - chrono::Duration::days(planner::RATE_WINDOW_DAYS - 1))
+            .format("%Y-%m-%d")
+            .to_string();
+
+        let subjects: Vec<SubjectPlan> = self.get_subject_targets()
+            .into_iter()
+            .map(|(subject, target)| {
+                let studied = self.get_subject_studied(&subject, "1970-01-01", &today_str);
+                let recent = self.get_subject_studied(&subject, &window_start, &today_str);
+                let subject_exam = countdowns.iter()
+                    .find(|c| c.subject == subject)
+                    .and_then(|c| parse(&c.date));
+                planner::plan_subject(&subject, target, studied, recent, today, subject_exam)
+            })
+            .collect();
+
+        let courses = self.get_courses();
+        let exams: Vec<SubjectPlan> = self.get_exams()
+            .into_iter()
+            .filter(|e| e.target_seconds > 0 && e.date >= today_str)
+            .map(|exam| {
+                let exam_courses = Self::exam_courses(&exam, &courses);
+                let studied = exam_courses.iter()
+                    .map(|c| self.get_course_studied(&c.id, "1970-01-01", &today_str))
+                    .sum();
+                let recent = exam_courses.iter()
+                    .map(|c| self.get_course_studied(&c.id, &window_start, &today_str))
+                    .sum();
+                planner::plan_subject(&exam.name, exam.target_seconds, studied, recent, today, parse(&exam.date))
+            })
+            .collect();
+
+        ExamPlan {
+            exam_date: exam_date.map(|d| d.format("%Y-%m-%d").to_string()),
+            days_left: exam_date.map(|d| (d - today).num_days().max(0)),
+            warnings: subjects.iter().chain(exams.iter()).filter_map(|p| p.warning.clone()).collect(),
+            subjects,
+            exams,
+        }
     }
 
     pub fn get_setting(&self, key: &str) -> Option<String> {
@@ -321,6 +2145,26 @@ impl Database {
             .unwrap();
     }
 
+    // 本机的设备 id，首次使用时生成，不参与同步
+    pub fn device_id(&self) -> String {
+        if let Some(id) = self.get_setting("device_id").filter(|v| !v.is_empty()) {
+            return id;
+        }
+        let id = Uuid::new_v4().to_string();
+        self.set_setting("device_id", &id);
+        id
+    }
+
+    // 日历订阅和 CSV 导出链接中的令牌，首次使用时生成
+    pub fn calendar_token(&self) -> String {
+        if let Some(token) = self.get_setting("calendar_token").filter(|v| !v.is_empty()) {
+            return token;
+        }
+        let token = Uuid::new_v4().simple().to_string();
+        self.set_setting("calendar_token", &token);
+        token
+    }
+
     pub fn get_all_study_logs(&self) -> Vec<StudyLog> {
         let mut stmt = self.conn
             .prepare("SELECT id, course_id, date, duration FROM study_logs")
@@ -338,4 +2182,427 @@ impl Database {
         .filter_map(|r| r.ok())
         .collect()
     }
+
+    // 同步用的 JSON 字段名
+    fn sync_field(table: &str) -> &'static str {
+        match table {
+            "courses" => "courses",
+            "study_logs" => "studyLogs",
+            "sessions" => "sessions",
+            _ => "settings",
+        }
+    }
+
+    // 按 merge::columns 读出的同步数据，filter 为 WHERE 条件
+    fn sync_rows(&self, table: &str, filter: &str, param: &dyn rusqlite::ToSql) -> Vec<serde_json::Value> {
+        let columns = merge::columns(table);
+        let mut stmt = self.conn
+            .prepare(&format!("SELECT {} FROM {} WHERE {}", columns.join(", "), table, filter))
+            .unwrap();
+        stmt.query_map([param], |row| {
+            let mut object = serde_json::Map::new();
+            for (i, column) in columns.iter().enumerate() {
+                object.insert(column.to_string(), backup::to_json(row.get_ref(i)?));
+            }
+            Ok(serde_json::Value::Object(object))
+        })
+        .unwrap()
+        .filter_map(|r| r.ok())
+        .collect()
+    }
+
+    fn sync_deleted_at(&self, table: &str, id: &str) -> Option<i64> {
+        self.conn
+            .query_row(
+                "SELECT deleted_at FROM sync_tombstones WHERE table_name = ? AND row_id = ?",
+                params![table, id],
+                |row| row.get(0),
+            )
+            .ok()
+    }
+
+    // since（毫秒）之后修改或删除的数据，设置只包含 settings 中列出的项
+    pub fn get_sync_changes(&self, since: i64, settings: &[&str]) -> serde_json::Value {
+        let mut changes = serde_json::Map::new();
+        for (table, _) in SYNC_TABLES.iter().filter(|(t, _)| *t != "settings") {
+            changes.insert(Self::sync_field(table).to_string(), self.sync_rows(table, "updated_at >= ?", &since).into());
+        }
+
+        let mut values = serde_json::Map::new();
+        let mut updated_at = serde_json::Map::new();
+        for row in self.sync_rows("settings", "updated_at >= ?", &since) {
+            let key = row["key"].as_str().unwrap_or_default().to_string();
+            if settings.contains(&key.as_str()) {
+                values.insert(key.clone(), row["value"].clone());
+                updated_at.insert(key, row["updated_at"].clone());
+            }
+        }
+        changes.insert("settings".to_string(), values.into());
+        changes.insert("settingsUpdatedAt".to_string(), updated_at.into());
+
+        let mut deleted = serde_json::Map::new();
+        for (table, _) in SYNC_TABLES {
+            deleted.insert(Self::sync_field(table).to_string(), serde_json::Value::Array(Vec::new()));
+        }
+        let tombstones: Vec<(String, String, i64)> = self.conn
+            .prepare("SELECT table_name, row_id, deleted_at FROM sync_tombstones WHERE deleted_at >= ?")
+            .unwrap()
+            .query_map([since], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .filter_map(|r| r.ok())
+            .collect();
+        for (table, id, at) in tombstones {
+            if table == "settings" && !settings.contains(&id.as_str()) {
+                continue;
+            }
+            if let Some(list) = deleted.get_mut(Self::sync_field(&table)).and_then(|v| v.as_array_mut()) {
+                list.push(serde_json::json!({ "id": id, "deleted_at": at }));
+            }
+        }
+        changes.insert("deleted".to_string(), deleted.into());
+        serde_json::Value::Object(changes)
+    }
+
+    // 写入一条远端数据，带上远端的修改时间，不会在下次同步时被当作本地修改
+    fn write_sync_row(&self, table: &str, row: &serde_json::Value) -> Result<usize, String> {
+        // 缺少修改时间的行（如旧版本上传的数据）按 0 处理，与 merge::remote_wins 一致
+        let updated_at = row["updated_at"].as_i64().unwrap_or(0);
+        if table == "courses" {
+            let str_of = |field: &str| row[field].as_str().unwrap_or_default().to_string();
+            let subject_id = self.ensure_subject(&str_of("subject"));
+            let sql = if self.get_course(&str_of("id")).is_some() {
+                "UPDATE courses SET name = ?2, subject = ?3, url_pattern = ?4, subject_id = ?5, updated_at = ?6 WHERE id = ?1"
+            } else {
+                "INSERT INTO courses (id, name, subject, url_pattern, subject_id, updated_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)"
+            };
+            return self.conn
+                .execute(
+                    sql,
+                    params![str_of("id"), str_of("name"), str_of("subject"), str_of("url_pattern"), subject_id, updated_at],
+                )
+                .map_err(|e| e.to_string());
+        }
+
+        let columns = merge::columns(table);
+        let mut values: Vec<rusqlite::types::Value> = columns
+            .iter()
+            .map(|c| if *c == "updated_at" { updated_at.into() } else { backup::from_json(&row[*c]) })
+            .collect();
+        // 章节不参与同步，本地没有的章节不保留
+        if let Some(i) = columns.iter().position(|c| *c == "chapter_id") {
+            let exists = row["chapter_id"]
+                .as_str()
+                .is_some_and(|id| self.conn.query_row("SELECT 1 FROM chapters WHERE id = ?", [id], |_| Ok(())).is_ok());
+            if !exists {
+                values[i] = rusqlite::types::Value::Null;
+            }
+        }
+        let sql = format!(
+            "INSERT INTO {} ({}) VALUES ({}) ON CONFLICT(id) DO UPDATE SET {}",
+            table,
+            columns.join(", "),
+            vec!["?"; columns.len()].join(", "),
+            columns.iter().filter(|c| **c != "id").map(|c| format!("{0} = excluded.{0}", c)).collect::<Vec<_>>().join(", ")
+        );
+        self.conn
+            .execute(&sql, rusqlite::params_from_iter(values))
+            .map_err(|e| e.to_string())
+    }
+
+    // 同一条记录只保留最近一次冲突
+    fn record_sync_conflict(&self, table: &str, id: &str, local: &serde_json::Value, remote: &serde_json::Value, remote_wins: bool) -> Result<(), String> {
+        self.conn
+            .execute("DELETE FROM sync_conflicts WHERE table_name = ? AND row_id = ?", params![table, id])
+            .map_err(|e| e.to_string())?;
+        self.conn
+            .execute(
+                "INSERT INTO sync_conflicts (table_name, row_id, local, remote, kept, detected_at) VALUES (?, ?, ?, ?, ?, ?)",
+                params![
+                    table,
+                    id,
+                    merge::content(table, local).to_string(),
+                    merge::content(table, remote).to_string(),
+                    if remote_wins { "remote" } else { "local" },
+                    chrono::Utc::now().timestamp()
+                ],
+            )
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+
+    // 写入其它设备的变更，按 merge 的规则合并；返回写入的内容
+    // since 为本地未同步修改的起点，本地在此之后也修改过且内容不同的记录作为冲突保存
+    pub fn apply_sync_changes(&self, changes: &serde_json::Value, settings: &[&str], since: i64) -> Result<SyncPreview, String> {
+        let tx = self.conn.unchecked_transaction().map_err(|e| e.to_string())?;
+        let result = self.merge_sync_changes(changes, settings, since)?;
+        tx.commit().map_err(|e| e.to_string())?;
+        Ok(result)
+    }
+
+    // 预览：在事务中合并后回滚，不修改本地数据
+    pub fn preview_sync_changes(&self, changes: &serde_json::Value, settings: &[&str], since: i64) -> Result<SyncPreview, String> {
+        let _tx = self.conn.unchecked_transaction().map_err(|e| e.to_string())?;
+        self.merge_sync_changes(changes, settings, since)
+    }
+
+    // 只在 apply / preview 的事务中调用，出错回滚时 sync_applying 的标记一并撤销
+    fn merge_sync_changes(&self, changes: &serde_json::Value, settings: &[&str], since: i64) -> Result<SyncPreview, String> {
+        self.conn.execute("INSERT INTO sync_applying (active) VALUES (1)", []).map_err(|e| e.to_string())?;
+        let result = self.merge_sync_rows(changes, settings, since);
+        self.conn.execute("DELETE FROM sync_applying", []).map_err(|e| e.to_string())?;
+        result
+    }
+
+    fn merge_sync_rows(&self, changes: &serde_json::Value, settings: &[&str], since: i64) -> Result<SyncPreview, String> {
+        let str_of = |v: &serde_json::Value, field: &str| v[field].as_str().unwrap_or_default().to_string();
+        let mut result = SyncPreview {
+            new_courses: Vec::new(),
+            updated_courses: Vec::new(),
+            study_logs: 0,
+            sessions: 0,
+            duration: 0,
+            settings: Vec::new(),
+            deleted: 0,
+            conflicts: 0,
+        };
+
+        // 先写课程，每日记录和会话依赖课程
+        for (table, key) in SYNC_TABLES.iter().filter(|(t, _)| *t != "settings") {
+            for remote in changes[Self::sync_field(table)].as_array().into_iter().flatten() {
+                let id = str_of(remote, "id");
+                // 课程在本地不存在时跳过（外键约束）
+                if id.is_empty() || (*table != "courses" && self.get_course(&str_of(remote, "course_id")).is_none()) {
+                    continue;
+                }
+                let at = remote["updated_at"].as_i64().unwrap_or(0);
+                let local = self.sync_rows(table, &format!("{} = ?", key), &id).pop();
+                let wins = match &local {
+                    Some(local) => merge::remote_wins(table, local, remote),
+                    None => self.sync_deleted_at(table, &id).is_none_or(|deleted| deleted < at),
+                };
+                if let Some(local) = &local {
+                    let pending = local["updated_at"].as_i64().unwrap_or(0) >= since;
+                    if pending && merge::can_conflict(table) && merge::differs(table, local, remote) {
+                        self.record_sync_conflict(table, &id, local, remote, wins)?;
+                        result.conflicts += 1;
+                    }
+                }
+                if !wins || self.write_sync_row(table, remote)? == 0 {
+                    continue;
+                }
+                match *table {
+                    "courses" if local.is_some() => result.updated_courses.push(str_of(remote, "name")),
+                    "courses" => result.new_courses.push(str_of(remote, "name")),
+                    "study_logs" => {
+                        result.study_logs += 1;
+                        let before = local.as_ref().and_then(|l| l["duration"].as_i64()).unwrap_or(0);
+                        result.duration += remote["duration"].as_i64().unwrap_or(0) - before;
+                    }
+                    _ => result.sessions += 1,
+                }
+            }
+        }
+
+        for (key, value) in changes["settings"].as_object().into_iter().flatten() {
+            if !settings.contains(&key.as_str()) {
+                continue;
+            }
+            let value = match value {
+                serde_json::Value::String(v) => v.clone(),
+                other => other.to_string(),
+            };
+            let at = changes["settingsUpdatedAt"][key].as_i64().unwrap_or(0);
+            let remote = serde_json::json!({ "value": value, "updated_at": at });
+            let wins = match self.sync_rows("settings", "key = ?", key).pop() {
+                Some(local) => merge::remote_wins("settings", &local, &remote),
+                None => self.sync_deleted_at("settings", key).is_none_or(|deleted| deleted < at),
+            };
+            if !wins {
+                continue;
+            }
+            self.conn
+                .execute(
+                    "INSERT INTO settings (key, value, updated_at) VALUES (?1, ?2, ?3)
+                     ON CONFLICT(key) DO UPDATE SET value = ?2, updated_at = ?3",
+                    params![key, value, at],
+                )
+                .map_err(|e| e.to_string())?;
+            result.settings.push(key.clone());
+            match key.as_str() {
+                "daily_goal" => {
+                    let today = chrono::Local::now().format("%Y-%m-%d").to_string();
+                    self.conn
+                        .execute(
+                            "INSERT OR REPLACE INTO goal_history (effective_date, seconds) VALUES (?, ?)",
+                            params![today, value.parse::<i64>().unwrap_or(0)],
+                        )
+                        .map_err(|e| e.to_string())?;
+                }
+                "exam_date" if !value.is_empty() => self.set_default_exam_date(&value),
+                _ => {}
+            }
+        }
+
+        // 删除：本地在删除之后没有再修改过才删除
+        for (table, key) in SYNC_TABLES {
+            for entry in changes["deleted"][Self::sync_field(table)].as_array().into_iter().flatten() {
+                let id = str_of(entry, "id");
+                let at = entry["deleted_at"].as_i64().unwrap_or(0);
+                if table == "settings" && !settings.contains(&id.as_str()) {
+                    continue;
+                }
+                let local = self.sync_rows(table, &format!("{} = ?", key), &id).pop();
+                match local.and_then(|l| l["updated_at"].as_i64()) {
+                    Some(local) if local <= at => {}
+                    _ => continue,
+                }
+                if table == "courses" {
+                    self.delete_course(&id);
+                } else {
+                    self.conn
+                        .execute(&format!("DELETE FROM {} WHERE {} = ?", table, key), [&id])
+                        .map_err(|e| e.to_string())?;
+                }
+                self.conn
+                    .execute(
+                        "UPDATE sync_tombstones SET deleted_at = ? WHERE table_name = ? AND row_id = ?",
+                        params![at, table, id],
+                    )
+                    .map_err(|e| e.to_string())?;
+                result.deleted += 1;
+            }
+        }
+        Ok(result)
+    }
+
+    pub fn count_sync_conflicts(&self) -> i64 {
+        self.conn
+            .query_row("SELECT COUNT(*) FROM sync_conflicts", [], |row| row.get(0))
+            .unwrap_or(0)
+    }
+
+    // 待处理的同步冲突，最近的在前；label 为课程名或会话的课程名和日期
+    pub fn get_sync_conflicts(&self) -> Vec<SyncConflict> {
+        let mut stmt = self.conn
+            .prepare(
+                "SELECT c.id, c.table_name, c.row_id, COALESCE(co.name, sc.name || ' ' || s.date, c.row_id),
+                        c.local, c.remote, c.kept, c.detected_at
+                 FROM sync_conflicts c
+                 LEFT JOIN courses co ON c.table_name = 'courses' AND co.id = c.row_id
+                 LEFT JOIN sessions s ON c.table_name = 'sessions' AND s.id = c.row_id
+                 LEFT JOIN courses sc ON sc.id = s.course_id
+                 ORDER BY c.detected_at DESC, c.id DESC",
+            )
+            .unwrap();
+        stmt.query_map([], |row| {
+            Ok(SyncConflict {
+                id: row.get(0)?,
+                table_name: row.get(1)?,
+                row_id: row.get(2)?,
+                label: row.get(3)?,
+                local: serde_json::from_str(&row.get::<_, String>(4)?).unwrap_or_default(),
+                remote: serde_json::from_str(&row.get::<_, String>(5)?).unwrap_or_default(),
+                kept: row.get(6)?,
+                detected_at: row.get(7)?,
+            })
+        })
+        .unwrap()
+        .filter_map(|r| r.ok())
+        .collect()
+    }
+
+    // 处理冲突：use_other 时改用合并时被覆盖的版本（作为本机的新修改同步出去），否则保留当前版本
+    pub fn resolve_sync_conflict(&self, id: i64, use_other: bool) -> Result<(), String> {
+        let (table, row_id, local, remote, kept): (String, String, String, String, String) = self.conn
+            .query_row(
+                "SELECT table_name, row_id, local, remote, kept FROM sync_conflicts WHERE id = ?",
+                [id],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?)),
+            )
+            .map_err(|_| "冲突记录不存在".to_string())?;
+
+        if use_other {
+            let other: serde_json::Value =
+                serde_json::from_str(if kept == "remote" { &local } else { &remote }).map_err(|e| e.to_string())?;
+            let fields = merge::content_fields(&table);
+            let sql = format!(
+                "UPDATE {} SET {} WHERE id = ?",
+                table,
+                fields.iter().map(|f| format!("{} = ?", f)).collect::<Vec<_>>().join(", ")
+            );
+            let values = fields
+                .iter()
+                .map(|f| backup::from_json(&other[*f]))
+                .chain(std::iter::once(rusqlite::types::Value::Text(row_id.clone())));
+            let updated = self.conn
+                .execute(&sql, rusqlite::params_from_iter(values))
+                .map_err(|e| e.to_string())?;
+            if updated == 0 {
+                return Err("记录已被删除".to_string());
+            }
+            if table == "courses" {
+                let subject_id = self.ensure_subject(other["subject"].as_str().unwrap_or_default());
+                self.conn
+                    .execute("UPDATE courses SET subject_id = ? WHERE id = ?", params![subject_id, row_id])
+                    .map_err(|e| e.to_string())?;
+            }
+        }
+
+        self.conn
+            .execute("DELETE FROM sync_conflicts WHERE id = ?", [id])
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn pulled_rows_without_updated_at_are_not_sent_back() {
+        let db = Database::in_memory().unwrap();
+        db.add_subject("数学", "", None).unwrap();
+        let subject_id = db.get_subjects()[0].id.clone();
+        let course = db.add_course("高数", &subject_id, "", "", "").unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        let since = chrono::Utc::now().timestamp_millis();
+
+        // 旧版本上传的数据没有 updated_at
+        let changes = json!({
+            "courses": [{ "id": "remote", "name": "线代", "subject": "数学", "url_pattern": "" }],
+            "sessions": [{
+                "id": "s1", "course_id": course, "date": "2026-10-01", "start_time": 0, "end_time": 60,
+                "duration": 60, "note": "", "titles": "", "device_id": "other"
+            }],
+            "settings": { "daily_goal": "3600" },
+        });
+        db.apply_sync_changes(&changes, &["daily_goal"], since).unwrap();
+        assert!(db.get_course("remote").is_some());
+
+        let outgoing = db.get_sync_changes(since, &["daily_goal"]);
+        assert_eq!(outgoing["courses"], json!([]));
+        assert_eq!(outgoing["sessions"], json!([]));
+        assert_eq!(outgoing["settings"], json!({}));
+
+        // 之后的本机修改仍记录修改时间
+        db.update_course("remote", "线性代数", &subject_id, "", "", "").unwrap();
+        let outgoing = db.get_sync_changes(since, &["daily_goal"]);
+        assert_eq!(outgoing["courses"][0]["id"], "remote");
+    }
+
+    #[test]
+    fn failed_session_leaves_no_study_log() {
+        let db = Database::in_memory().unwrap();
+        db.add_subject("数学", "", None).unwrap();
+        let subject_id = db.get_subjects()[0].id.clone();
+        let course = db.add_course("高数", &subject_id, "", "", "").unwrap();
+
+        // 写入标签失败时，之前写入的学习记录和会话一起回滚
+        db.conn.execute_batch("DROP TABLE session_tags").unwrap();
+        assert!(db.record_session(&course, None, &["t1".to_string()], "2026-10-01", 0, 600).is_err());
+        assert_eq!(db.get_studied_duration("2026-10-01"), 0);
+        assert!(db.get_sessions("2026-10-01", "2026-10-01").is_empty());
+    }
 }
@@ -0,0 +1,67 @@
+use crate::{ExportLog, ExportSession};
+use chrono::{Local, TimeZone};
+
+// 带 BOM，Excel 打开时才能正确识别 UTF-8 中文
+const BOM: &str = "\u{feff}";
+
+fn local_time(timestamp: i64) -> String {
+    Local
+        .timestamp_opt(timestamp, 0)
+        .single()
+        .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+        .unwrap_or_default()
+}
+
+fn finish(writer: csv::Writer<Vec<u8>>) -> Result<String, String> {
+    let bytes = writer.into_inner().map_err(|e| e.to_string())?;
+    let body = String::from_utf8(bytes).map_err(|e| e.to_string())?;
+    Ok(format!("{}{}", BOM, body))
+}
+
+// 每日记录：日期、课程、科目、时长
+pub fn logs_csv(rows: &[ExportLog]) -> Result<String, String> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer
+        .write_record(["date", "course", "subject", "seconds", "minutes"])
+        .map_err(|e| e.to_string())?;
+    for row in rows {
+        writer
+            .write_record([
+                row.date.clone(),
+                row.course_name.clone(),
+                row.subject.clone(),
+                row.duration.to_string(),
+                (row.duration / 60).to_string(),
+            ])
+            .map_err(|e| e.to_string())?;
+    }
+    finish(writer)
+}
+
+// 会话明细：起止时间为本地时间，标签以分号分隔
+pub fn sessions_csv(rows: &[ExportSession]) -> Result<String, String> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer
+        .write_record([
+            "date", "start", "end", "course", "subject", "chapter", "seconds", "minutes", "focus", "tags", "note",
+        ])
+        .map_err(|e| e.to_string())?;
+    for row in rows {
+        writer
+            .write_record([
+                row.date.clone(),
+                local_time(row.start_time),
+                local_time(row.end_time),
+                row.course_name.clone(),
+                row.subject.clone(),
+                row.chapter_name.clone().unwrap_or_default(),
+                row.duration.to_string(),
+                (row.duration / 60).to_string(),
+                row.focus.map(|f| f.to_string()).unwrap_or_default(),
+                row.tags.join(";"),
+                row.note.clone(),
+            ])
+            .map_err(|e| e.to_string())?;
+    }
+    finish(writer)
+}
@@ -0,0 +1,49 @@
+use crate::GoalProgress;
+use chrono::{Datelike, Duration, NaiveDate};
+
+// 目标周期：daily / weekly（ISO 周，周一开始）/ monthly
+pub fn period_range(period: &str, date: NaiveDate) -> (NaiveDate, NaiveDate) {
+    match period {
+        "weekly" => {
+            let start = date - Duration::days(date.weekday().num_days_from_monday() as i64);
+            (start, start + Duration::days(6))
+        }
+        "monthly" => {
+            let start = date.with_day(1).unwrap();
+            let next = if date.month() == 12 {
+                NaiveDate::from_ymd_opt(date.year() + 1, 1, 1)
+            } else {
+                NaiveDate::from_ymd_opt(date.year(), date.month() + 1, 1)
+            }
+            .unwrap();
+            (start, next - Duration::days(1))
+        }
+        _ => (date, date),
+    }
+}
+
+pub fn is_valid_period(period: &str) -> bool {
+    matches!(period, "daily" | "weekly" | "monthly")
+}
+
+pub fn is_valid_scope(scope: &str) -> bool {
+    matches!(scope, "subject" | "course")
+}
+
+fn period_label(period: &str) -> &'static str {
+    match period {
+        "weekly" => "本周",
+        "monthly" => "本月",
+        _ => "今日",
+    }
+}
+
+// 通知用的进度文字，如 "数学：45/60 分钟（今日）"、"物理：3/5 小时（本周）"
+pub fn format_progress(p: &GoalProgress) -> String {
+    let amount = if p.goal >= 3600 && p.goal % 3600 == 0 {
+        format!("{}/{} 小时", p.studied / 3600, p.goal / 3600)
+    } else {
+        format!("{}/{} 分钟", p.studied / 60, p.goal / 60)
+    };
+    format!("{}：{}（{}）", p.label, amount, period_label(&p.period))
+}
@@ -0,0 +1,41 @@
+use crate::{SubjectHeatmap, TimeHeatmap};
+use chrono::{Datelike, Local, TimeZone, Timelike};
+use std::collections::BTreeMap;
+
+fn empty_grid() -> Vec<Vec<i64>> {
+    vec![vec![0; 24]; 7]
+}
+
+// 会话按本地小时边界拆分后计入 grid[星期][小时]，星期 0 = 周一
+fn add_session(grid: &mut [Vec<i64>], start: i64, end: i64) {
+    let mut t = start;
+    while t < end {
+        let Some(local) = Local.timestamp_opt(t, 0).earliest() else {
+            break;
+        };
+        let hour_start = t - (local.minute() * 60 + local.second()) as i64;
+        let next = (hour_start + 3600).min(end);
+        let weekday = local.weekday().num_days_from_monday() as usize;
+        grid[weekday][local.hour() as usize] += next - t;
+        t = next;
+    }
+}
+
+// sessions 为 (科目, 开始时间, 结束时间)
+pub fn bin_sessions(sessions: &[(String, i64, i64)]) -> TimeHeatmap {
+    let mut grid = empty_grid();
+    let mut by_subject: BTreeMap<String, Vec<Vec<i64>>> = BTreeMap::new();
+
+    for (subject, start, end) in sessions {
+        add_session(&mut grid, *start, *end);
+        add_session(by_subject.entry(subject.clone()).or_insert_with(empty_grid), *start, *end);
+    }
+
+    TimeHeatmap {
+        grid,
+        subjects: by_subject
+            .into_iter()
+            .map(|(subject, grid)| SubjectHeatmap { subject, grid })
+            .collect(),
+    }
+}
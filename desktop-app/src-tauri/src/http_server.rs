@@ -1,24 +1,63 @@
-use crate::{Course, SharedState};
+use crate::{tracker, Course, SharedState};
 use serde::{Deserialize, Serialize};
 use std::convert::Infallible;
-use std::sync::atomic::{AtomicBool, Ordering};
 use warp::Filter;
-use winrt_notification::{Duration, Sound, Toast};
-
-// 全局标志：是否需要触发同步
-pub static SYNC_TRIGGER: AtomicBool = AtomicBool::new(false);
-
-pub fn should_sync() -> bool {
-    SYNC_TRIGGER.swap(false, Ordering::SeqCst)
-}
 
 #[derive(Deserialize)]
 struct StatusReport {
     course_id: String,
     active: bool,
     timestamp: i64,
-    #[allow(dead_code)]
+    #[serde(default)]
     url: String,
+    #[serde(default)]
+    title: String,
+}
+
+#[derive(Deserialize)]
+struct NoteReport {
+    session_id: Option<String>,
+    note: String,
+    focus: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct ExportQuery {
+    token: Option<String>,
+    kind: Option<String>,
+    start_date: Option<String>,
+    end_date: Option<String>,
+    subject_id: Option<String>,
+    tag: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CalendarQuery {
+    token: Option<String>,
+    start_date: Option<String>,
+    end_date: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct SearchQuery {
+    q: String,
+    limit: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct StatisticsQuery {
+    start_date: Option<String>,
+    end_date: Option<String>,
+    subject_id: Option<String>,
+    tag: Option<String>,
+    granularity: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct HeatmapQuery {
+    start_date: Option<String>,
+    end_date: Option<String>,
+    subject_id: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -53,22 +92,24 @@ fn with_state(
     warp::any().map(move || state.clone())
 }
 
-fn send_notification(title: &str, body: &str, state: &SharedState) {
-    // 检查是否启用通知
-    let enabled = state.lock().db.get_setting("notifications_enabled")
-        .map(|v| v == "true")
-        .unwrap_or(true); // 默认开启
-    
-    if !enabled {
-        return;
+// 浏览器扩展的请求带 chrome-extension:// 来源，网页发起的跨站请求带网页的来源，一律拒绝；
+// 不带来源的请求来自浏览器以外的本机程序
+fn from_extension(origin: &Option<String>) -> bool {
+    match origin {
+        Some(origin) => origin.starts_with("chrome-extension://"),
+        None => true,
     }
-    
-    let _ = Toast::new(Toast::POWERSHELL_APP_ID)
-        .title(title)
-        .text1(body)
-        .duration(Duration::Short)
-        .sound(Some(Sound::Default))
-        .show();
+}
+
+fn forbidden() -> warp::reply::WithStatus<warp::reply::Json> {
+    warp::reply::with_status(
+        warp::reply::json(&ApiResponse::<()> {
+            success: false,
+            data: None,
+            message: Some("仅允许浏览器扩展访问".to_string()),
+        }),
+        warp::http::StatusCode::FORBIDDEN,
+    )
 }
 
 pub fn start_server(state: SharedState) {
@@ -104,73 +145,7 @@ pub fn start_server(state: SharedState) {
             .and(warp::body::json())
             .and(with_state(state.clone()))
             .map(|report: StatusReport, state: SharedState| {
-                let mut s = state.lock();
-                let today = chrono::Local::now().format("%Y-%m-%d").to_string();
-
-                if report.active {
-                    let switched_course = s.current_course_id.as_ref() != Some(&report.course_id);
-                    let is_new_session = s.session_start.is_none();
-                    
-                    // 切换课程时，保存之前课程的学习时长
-                    if switched_course && !is_new_session {
-                        if let (Some(prev_id), Some(start)) = (&s.current_course_id, s.session_start) {
-                            let duration = report.timestamp - start;
-                            if duration > 0 {
-                                s.db.add_study_log(prev_id, &today, duration);
-                            }
-                        }
-                    }
-                    
-                    // 发送通知（仅在开始或切换时）
-                    if is_new_session || switched_course {
-                        if let Some(course) = s.db.get_course(&report.course_id) {
-                            if is_new_session {
-                                drop(s);
-                                send_notification("开始学习", &format!("正在学习：{}", course.name), &state);
-                                s = state.lock();
-                            } else {
-                                drop(s);
-                                send_notification("切换课程", &format!("正在学习：{}", course.name), &state);
-                                s = state.lock();
-                            }
-                        }
-                        // 只在开始或切换时重置 session_start
-                        s.session_start = Some(report.timestamp);
-                    }
-                    
-                    s.current_course_id = Some(report.course_id.clone());
-                    s.last_report_time = Some(report.timestamp);
-                    
-                } else {
-                    if let (Some(course_id), Some(start)) = (&s.current_course_id, s.session_start) {
-                        if course_id == &report.course_id {
-                            let duration = report.timestamp - start;
-                            if duration > 0 {
-                                s.db.add_study_log(course_id, &today, duration);
-                                
-                                if let Some(course) = s.db.get_course(course_id) {
-                                    let total_today = s.db.get_studied_duration(&today);
-                                    let mins = total_today / 60;
-                                    let msg = format!("{}：今日已学习 {} 分钟", course.name, mins);
-                                    drop(s);
-                                    send_notification("学习暂停", &msg, &state);
-                                    s = state.lock();
-                                }
-                                
-                                // 检查是否启用暂停时同步
-                                let sync_on_pause = s.db.get_setting("sync_on_pause")
-                                    .map(|v| v == "true")
-                                    .unwrap_or(false);
-                                if sync_on_pause {
-                                    SYNC_TRIGGER.store(true, Ordering::SeqCst);
-                                }
-                            }
-                            s.current_course_id = None;
-                            s.session_start = None;
-                            s.last_report_time = None;
-                        }
-                    }
-                }
+                tracker::report_status(&state, &report.course_id, report.active, report.timestamp, &report.url, &report.title);
 
                 warp::reply::json(&ApiResponse::<()> {
                     success: true,
@@ -179,6 +154,185 @@ pub fn start_server(state: SharedState) {
                 })
             });
 
+        // POST /sessions/note，未指定 session_id 时备注当前或最近一次会话
+        // 只允许扩展访问，网页无法伪造请求改写备注
+        let post_note = warp::path!("sessions" / "note")
+            .and(warp::post())
+            .and(warp::header::optional::<String>("origin"))
+            .and(warp::body::json())
+            .and(with_state(state.clone()))
+            .map(|origin: Option<String>, report: NoteReport, state: SharedState| {
+                if !from_extension(&origin) {
+                    return forbidden();
+                }
+                let result = tracker::annotate_session(&mut state.lock(), report.session_id.as_deref(), report.note, report.focus);
+                warp::reply::with_status(
+                    warp::reply::json(&ApiResponse::<()> {
+                        success: result.is_ok(),
+                        data: None,
+                        message: Some(result.err().unwrap_or_else(|| "Note saved".to_string())),
+                    }),
+                    warp::http::StatusCode::OK,
+                )
+            });
+
+        // GET /export.csv?token=&kind=logs|sessions&start_date=&end_date=&subject_id=&tag=
+        // 与日历订阅相同，需要本机的令牌且不返回 CORS 头
+        let get_export = warp::path("export.csv")
+            .and(warp::get())
+            .and(warp::query::<ExportQuery>())
+            .and(with_state(state.clone()))
+            .map(|q: ExportQuery, state: SharedState| {
+                let s = state.lock();
+                if q.token.as_deref() != Some(s.db.calendar_token().as_str()) {
+                    return warp::http::Response::builder()
+                        .status(warp::http::StatusCode::FORBIDDEN)
+                        .body("链接无效".to_string())
+                        .unwrap();
+                }
+                let kind = q.kind.unwrap_or_else(|| "logs".to_string());
+                let result = s.db.export_csv(&kind, q.start_date, q.end_date, q.subject_id, q.tag);
+                match result {
+                    Ok(csv) => warp::http::Response::builder()
+                        .header("Content-Type", "text/csv; charset=utf-8")
+                        .header("Content-Disposition", format!("attachment; filename=\"study-{}.csv\"", kind))
+                        .body(csv)
+                        .unwrap(),
+                    Err(message) => warp::http::Response::builder()
+                        .status(warp::http::StatusCode::BAD_REQUEST)
+                        .body(message)
+                        .unwrap(),
+                }
+            });
+
+        // GET /calendar.ics?token=&start_date=&end_date=，供日历应用订阅
+        // 需要本机的订阅令牌，且不返回 CORS 头，网页无法读取
+        let get_calendar = warp::path("calendar.ics")
+            .and(warp::get())
+            .and(warp::query::<CalendarQuery>())
+            .and(with_state(state.clone()))
+            .map(|q: CalendarQuery, state: SharedState| {
+                let s = state.lock();
+                if q.token.as_deref() != Some(s.db.calendar_token().as_str()) {
+                    return warp::http::Response::builder()
+                        .status(warp::http::StatusCode::FORBIDDEN)
+                        .body("订阅链接无效".to_string())
+                        .unwrap();
+                }
+                let calendar = s.db.export_ics(q.start_date, q.end_date);
+                warp::http::Response::builder()
+                    .header("Content-Type", "text/calendar; charset=utf-8")
+                    .header("Content-Disposition", "inline; filename=\"study.ics\"")
+                    .body(calendar)
+                    .unwrap()
+            });
+
+        // GET /search?q=，返回笔记等内容，只允许扩展访问，不返回 CORS 头
+        // （扩展有 host_permissions，不需要 CORS）
+        let get_search = warp::path("search")
+            .and(warp::get())
+            .and(warp::header::optional::<String>("origin"))
+            .and(warp::query::<SearchQuery>())
+            .and(with_state(state.clone()))
+            .map(|origin: Option<String>, q: SearchQuery, state: SharedState| {
+                if !from_extension(&origin) {
+                    return forbidden();
+                }
+                let hits = state.lock().db.search(&q.q, q.limit.unwrap_or(50));
+                warp::reply::with_status(
+                    warp::reply::json(&ApiResponse {
+                        success: true,
+                        data: Some(hits),
+                        message: None,
+                    }),
+                    warp::http::StatusCode::OK,
+                )
+            });
+
+        // GET /statistics?start_date=&end_date=&subject_id=&tag=&granularity=day|week|month|year
+        let get_statistics = warp::path("statistics")
+            .and(warp::get())
+            .and(warp::query::<StatisticsQuery>())
+            .and(with_state(state.clone()))
+            .map(|q: StatisticsQuery, state: SharedState| {
+                let stats = state.lock().db.get_statistics(q.start_date, q.end_date, q.subject_id, q.tag, q.granularity);
+                warp::reply::json(&ApiResponse {
+                    success: true,
+                    data: Some(stats),
+                    message: None,
+                })
+            });
+
+        // GET /analytics/heatmap?start_date=&end_date=&subject_id=
+        let get_heatmap = warp::path!("analytics" / "heatmap")
+            .and(warp::get())
+            .and(warp::query::<HeatmapQuery>())
+            .and(with_state(state.clone()))
+            .map(|q: HeatmapQuery, state: SharedState| {
+                let heatmap = state.lock().db.get_time_heatmap(q.start_date, q.end_date, q.subject_id);
+                warp::reply::json(&ApiResponse {
+                    success: true,
+                    data: Some(heatmap),
+                    message: None,
+                })
+            });
+
+        // GET /plan
+        let get_plan = warp::path("plan")
+            .and(warp::get())
+            .and(with_state(state.clone()))
+            .map(|state: SharedState| {
+                let today = chrono::Local::now().date_naive();
+                let plan = state.lock().db.get_exam_plan(today);
+                warp::reply::json(&ApiResponse {
+                    success: true,
+                    data: Some(plan),
+                    message: None,
+                })
+            });
+
+        // GET /exams/countdown
+        let get_exam_countdowns = warp::path!("exams" / "countdown")
+            .and(warp::get())
+            .and(with_state(state.clone()))
+            .map(|state: SharedState| {
+                let today = chrono::Local::now().date_naive();
+                let countdowns = state.lock().db.get_exam_countdowns(today);
+                warp::reply::json(&ApiResponse {
+                    success: true,
+                    data: Some(countdowns),
+                    message: None,
+                })
+            });
+
+        // GET /goals
+        let get_goals = warp::path("goals")
+            .and(warp::get())
+            .and(with_state(state.clone()))
+            .map(|state: SharedState| {
+                let today = chrono::Local::now().date_naive();
+                let progress = state.lock().db.get_goal_progress(today);
+                warp::reply::json(&ApiResponse {
+                    success: true,
+                    data: Some(progress),
+                    message: None,
+                })
+            });
+
+        // GET /streaks
+        let get_streaks = warp::path("streaks")
+            .and(warp::get())
+            .and(with_state(state.clone()))
+            .map(|state: SharedState| {
+                let today = chrono::Local::now().date_naive();
+                let streaks = state.lock().db.get_streaks(today);
+                warp::reply::json(&ApiResponse {
+                    success: true,
+                    data: Some(streaks),
+                    message: None,
+                })
+            });
+
         let health = warp::path("health")
             .and(warp::get())
             .map(|| warp::reply::json(&ApiResponse::<()> {
@@ -189,8 +343,18 @@ pub fn start_server(state: SharedState) {
 
         let routes = get_courses
             .or(post_status)
+            .or(get_statistics)
+            .or(get_heatmap)
+            .or(get_plan)
+            .or(get_exam_countdowns)
+            .or(get_goals)
+            .or(get_streaks)
             .or(health)
-            .with(cors);
+            .with(cors)
+            .or(get_calendar)
+            .or(get_export)
+            .or(get_search)
+            .or(post_note);
 
         println!("HTTP API server running on http://127.0.0.1:23333");
         warp::serve(routes).run(([127, 0, 0, 1], 23333)).await;
@@ -0,0 +1,164 @@
+use crate::{Exam, ExportSession, PlannedBlock};
+use chrono::{Datelike, Duration, NaiveDate, TimeZone, Utc};
+
+const PRODID: &str = "-//Study Monitor//Study Sessions//ZH";
+const WEEKDAYS: [&str; 7] = ["MO", "TU", "WE", "TH", "FR", "SA", "SU"];
+
+// 一行最多 75 个字节，超出部分折行，续行以空格开头
+const MAX_LINE_BYTES: usize = 75;
+
+// 文本值中的反斜杠、逗号、分号和换行需要转义
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace("\r\n", "\\n")
+        .replace('\n', "\\n")
+}
+
+fn fold(line: &str) -> String {
+    let mut result = String::new();
+    let mut width = 0;
+    for c in line.chars() {
+        if width + c.len_utf8() > MAX_LINE_BYTES {
+            result.push_str("\r\n ");
+            width = 1;
+        }
+        result.push(c);
+        width += c.len_utf8();
+    }
+    result.push_str("\r\n");
+    result
+}
+
+fn utc(timestamp: i64) -> String {
+    Utc.timestamp_opt(timestamp, 0)
+        .single()
+        .map(|t| t.format("%Y%m%dT%H%M%SZ").to_string())
+        .unwrap_or_default()
+}
+
+// 计划时间没有时区信息，按浮动时间（日历所在时区的本地时间）输出
+fn floating(date: NaiveDate, time: &str) -> String {
+    format!("{}T{}00", date.format("%Y%m%d"), time.replace(':', ""))
+}
+
+struct Event {
+    uid: String,
+    lines: Vec<String>,
+}
+
+impl Event {
+    fn new(uid: String) -> Self {
+        Event { uid, lines: Vec::new() }
+    }
+
+    fn prop(mut self, name: &str, value: String) -> Self {
+        self.lines.push(format!("{}:{}", name, value));
+        self
+    }
+
+    fn text(self, name: &str, value: &str) -> Self {
+        if value.trim().is_empty() {
+            return self;
+        }
+        let value = escape(value.trim());
+        self.prop(name, value)
+    }
+}
+
+// 学习会话：课程名为标题，科目为分类，章节、专注度和备注写入描述
+fn session_event(session: &ExportSession) -> Event {
+    let mut description = Vec::new();
+    if let Some(chapter) = &session.chapter_name {
+        description.push(format!("章节：{}", chapter));
+    }
+    if let Some(focus) = session.focus {
+        description.push(format!("专注度：{}/5", focus));
+    }
+    if !session.tags.is_empty() {
+        description.push(format!("标签：{}", session.tags.join("、")));
+    }
+    if !session.note.trim().is_empty() {
+        description.push(session.note.trim().to_string());
+    }
+    Event::new(format!("session-{}", session.id))
+        .prop("DTSTART", utc(session.start_time))
+        .prop("DTEND", utc(session.end_time))
+        .text("SUMMARY", &session.course_name)
+        .text("CATEGORIES", &session.subject)
+        .text("DESCRIPTION", &description.join("\n"))
+}
+
+// 考试：全天事件
+fn exam_event(exam: &Exam) -> Option<Event> {
+    let date = NaiveDate::parse_from_str(&exam.date, "%Y-%m-%d").ok()?;
+    let mut description = exam.subjects.join("、");
+    if exam.target_seconds > 0 {
+        if !description.is_empty() {
+            description.push('\n');
+        }
+        description.push_str(&format!("目标学习时长：{} 小时", exam.target_seconds / 3600));
+    }
+    Some(
+        Event::new(format!("exam-{}", exam.id))
+            .prop("DTSTART;VALUE=DATE", date.format("%Y%m%d").to_string())
+            .prop("DTEND;VALUE=DATE", (date + Duration::days(1)).format("%Y%m%d").to_string())
+            .text("SUMMARY", &exam.name)
+            .text("CATEGORIES", "考试")
+            .text("DESCRIPTION", &description),
+    )
+}
+
+// 计划块：单次的按日期输出，每周重复的从范围开始后的第一次出现起按周重复
+fn block_event(block: &PlannedBlock, course_name: &str, from: NaiveDate) -> Option<Event> {
+    let (date, rule) = match (&block.date, block.weekday) {
+        (Some(d), _) => (NaiveDate::parse_from_str(d, "%Y-%m-%d").ok()?, None),
+        (None, Some(weekday)) => {
+            let code = WEEKDAYS.get(weekday as usize)?;
+            let offset = (weekday as i64 - from.weekday().num_days_from_monday() as i64).rem_euclid(7);
+            (from + Duration::days(offset), Some(format!("FREQ=WEEKLY;BYDAY={}", code)))
+        }
+        _ => return None,
+    };
+    let mut event = Event::new(format!("plan-{}", block.id))
+        .prop("DTSTART", floating(date, &block.start_time))
+        .prop("DTEND", floating(date, &block.end_time))
+        .text("SUMMARY", &format!("计划：{}", course_name))
+        .text("CATEGORIES", "计划");
+    if let Some(rule) = rule {
+        event = event.prop("RRULE", rule);
+    }
+    Some(event)
+}
+
+pub fn calendar(
+    sessions: &[ExportSession],
+    exams: &[Exam],
+    blocks: &[(PlannedBlock, String)],
+    from: NaiveDate,
+) -> String {
+    let events: Vec<Event> = sessions
+        .iter()
+        .map(session_event)
+        .chain(exams.iter().filter_map(exam_event))
+        .chain(blocks.iter().filter_map(|(b, name)| block_event(b, name, from)))
+        .collect();
+
+    let stamp = utc(Utc::now().timestamp());
+    let mut out = String::new();
+    for line in ["BEGIN:VCALENDAR", "VERSION:2.0", &format!("PRODID:{}", PRODID), "CALSCALE:GREGORIAN", "X-WR-CALNAME:学习记录"] {
+        out.push_str(&fold(line));
+    }
+    for event in events {
+        out.push_str(&fold("BEGIN:VEVENT"));
+        out.push_str(&fold(&format!("UID:{}@study-monitor", event.uid)));
+        out.push_str(&fold(&format!("DTSTAMP:{}", stamp)));
+        for line in &event.lines {
+            out.push_str(&fold(line));
+        }
+        out.push_str(&fold("END:VEVENT"));
+    }
+    out.push_str(&fold("END:VCALENDAR"));
+    out
+}
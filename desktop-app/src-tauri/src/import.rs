@@ -0,0 +1,193 @@
+use crate::{ColumnMapping, ImportRow};
+use chrono::{Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone};
+use std::collections::HashMap;
+
+const DATE_FORMATS: [&str; 4] = ["%Y-%m-%d", "%Y/%m/%d", "%m/%d/%Y", "%d.%m.%Y"];
+const TIME_FORMATS: [&str; 4] = ["%H:%M:%S", "%H:%M", "%I:%M:%S %p", "%I:%M %p"];
+
+fn column(name: &str) -> Option<String> {
+    Some(name.to_string())
+}
+
+// 已知格式的列映射；custom 使用调用方提供的映射
+pub fn mapping_for(format: &str, custom: Option<ColumnMapping>) -> Result<ColumnMapping, String> {
+    match format {
+        // Toggl Track 明细导出
+        "toggl" => Ok(ColumnMapping {
+            date: "Start date".to_string(),
+            start_time: column("Start time"),
+            end_time: column("End time"),
+            duration: column("Duration"),
+            duration_unit: None,
+            project: "Project".to_string(),
+            subject: column("Client"),
+            description: column("Description"),
+            date_format: None,
+        }),
+        // Clockify 明细导出，日期默认为 月/日/年
+        "clockify" => Ok(ColumnMapping {
+            date: "Start Date".to_string(),
+            start_time: column("Start Time"),
+            end_time: column("End Time"),
+            duration: column("Duration (h)"),
+            duration_unit: None,
+            project: "Project".to_string(),
+            subject: column("Client"),
+            description: column("Description"),
+            date_format: Some("%m/%d/%Y".to_string()),
+        }),
+        // 本应用导出的每日记录或会话明细
+        "study_monitor" => Ok(ColumnMapping {
+            date: "date".to_string(),
+            start_time: column("start"),
+            end_time: column("end"),
+            duration: column("seconds"),
+            duration_unit: Some("seconds".to_string()),
+            project: "course".to_string(),
+            subject: column("subject"),
+            description: column("note"),
+            date_format: None,
+        }),
+        "custom" => custom.ok_or_else(|| "自定义格式需要提供列映射".to_string()),
+        _ => Err("不支持的导入格式".to_string()),
+    }
+}
+
+// 时长：带冒号的按 时:分[:秒]，纯数字按 unit（seconds / minutes / hours，默认秒）
+pub fn parse_duration(value: &str, unit: Option<&str>) -> Option<i64> {
+    let value = value.trim();
+    if value.contains(':') {
+        let parts: Vec<i64> = value.split(':').map(|p| p.trim().parse().ok()).collect::<Option<_>>()?;
+        return match parts[..] {
+            [h, m] => Some(h * 3600 + m * 60),
+            [h, m, s] => Some(h * 3600 + m * 60 + s),
+            _ => None,
+        };
+    }
+    let number: f64 = value.replace(',', ".").parse().ok()?;
+    let factor = match unit.unwrap_or("seconds") {
+        "minutes" => 60.0,
+        "hours" => 3600.0,
+        _ => 1.0,
+    };
+    Some((number * factor).round() as i64)
+}
+
+fn parse_date(value: &str, format: Option<&str>) -> Option<NaiveDate> {
+    let value = value.trim();
+    let value = value.split_whitespace().next().unwrap_or(value);
+    match format {
+        Some(f) => NaiveDate::parse_from_str(value, f).ok(),
+        None => DATE_FORMATS.iter().find_map(|f| NaiveDate::parse_from_str(value, f).ok()),
+    }
+}
+
+// 时间列可以只有时间，也可以是完整的 日期 时间
+fn parse_time(value: &str, date: NaiveDate, date_format: Option<&str>) -> Option<i64> {
+    let value = value.trim();
+    let datetime = TIME_FORMATS
+        .iter()
+        .find_map(|f| NaiveTime::parse_from_str(value, f).ok())
+        .map(|t| date.and_time(t))
+        .or_else(|| {
+            let (d, t) = value.split_once(' ')?;
+            let d = parse_date(d, date_format)?;
+            let t = TIME_FORMATS.iter().find_map(|f| NaiveTime::parse_from_str(t.trim(), f).ok())?;
+            Some(NaiveDateTime::new(d, t))
+        })?;
+    Local.from_local_datetime(&datetime).earliest().map(|t| t.timestamp())
+}
+
+// 解析 CSV，返回可导入的行和出错行的说明；行号从 2 开始（第 1 行为表头）
+pub fn parse(content: &str, mapping: &ColumnMapping) -> Result<(Vec<ImportRow>, Vec<String>), String> {
+    let content = content.trim_start_matches('\u{feff}');
+    let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(content.as_bytes());
+    let headers: HashMap<String, usize> = reader
+        .headers()
+        .map_err(|e| format!("无法读取表头：{}", e))?
+        .iter()
+        .enumerate()
+        .map(|(i, h)| (h.trim().to_lowercase(), i))
+        .collect();
+    let find = |name: &str| headers.get(&name.trim().to_lowercase()).copied();
+
+    let date_col = find(&mapping.date).ok_or_else(|| format!("缺少日期列：{}", mapping.date))?;
+    let project_col = find(&mapping.project).ok_or_else(|| format!("缺少项目列：{}", mapping.project))?;
+    let start_col = mapping.start_time.as_deref().and_then(find);
+    let end_col = mapping.end_time.as_deref().and_then(find);
+    let duration_col = mapping.duration.as_deref().and_then(find);
+    if duration_col.is_none() && (start_col.is_none() || end_col.is_none()) {
+        return Err("需要时长列，或同时提供开始和结束时间列".to_string());
+    }
+    let subject_col = mapping.subject.as_deref().and_then(find);
+    let description_col = mapping.description.as_deref().and_then(find);
+    let date_format = mapping.date_format.as_deref();
+
+    let mut rows = Vec::new();
+    let mut errors = Vec::new();
+    for (i, record) in reader.records().enumerate() {
+        let line = i + 2;
+        let record = match record {
+            Ok(r) => r,
+            Err(e) => {
+                errors.push(format!("第 {} 行：{}", line, e));
+                continue;
+            }
+        };
+        let get = |col: usize| record.get(col).map(|v| v.trim()).unwrap_or("");
+
+        let Some(date) = parse_date(get(date_col), date_format) else {
+            errors.push(format!("第 {} 行：无法识别日期 {}", line, get(date_col)));
+            continue;
+        };
+        let project = get(project_col);
+        if project.is_empty() {
+            errors.push(format!("第 {} 行：缺少项目", line));
+            continue;
+        }
+        let start_time = start_col.and_then(|c| parse_time(get(c), date, date_format));
+        let end_time = end_col.and_then(|c| parse_time(get(c), date, date_format));
+        let duration = duration_col
+            .and_then(|c| parse_duration(get(c), mapping.duration_unit.as_deref()))
+            .or_else(|| match (start_time, end_time) {
+                (Some(s), Some(e)) if e > s => Some(e - s),
+                _ => None,
+            });
+        let Some(duration) = duration.filter(|d| *d > 0) else {
+            errors.push(format!("第 {} 行：无法识别时长", line));
+            continue;
+        };
+
+        rows.push(ImportRow {
+            line,
+            date: date.format("%Y-%m-%d").to_string(),
+            start_time,
+            duration,
+            project: project.to_string(),
+            subject: subject_col.map(get).unwrap_or("").to_string(),
+            description: description_col.map(get).unwrap_or("").to_string(),
+            course_id: None,
+            existing: 0,
+            duplicate: false,
+        });
+    }
+    Ok((rows, errors))
+}
+
+pub fn read_file(path: &str, format: &str, custom: Option<ColumnMapping>) -> Result<(Vec<ImportRow>, Vec<String>), String> {
+    let mapping = mapping_for(format, custom)?;
+    let bytes = std::fs::read(path).map_err(|e| format!("读取文件失败：{}", e))?;
+    parse(&String::from_utf8_lossy(&bytes), &mapping)
+}
+
+// 去重用的键，与来源格式无关，同一条记录以不同格式导入也能识别
+pub fn entry_key(row: &ImportRow) -> String {
+    format!(
+        "{}|{}|{}|{}|{}",
+        row.date,
+        row.start_time.map(|t| t.to_string()).unwrap_or_default(),
+        row.project.to_lowercase(),
+        row.duration,
+        row.description
+    )
+}
@@ -1,15 +1,37 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+#[cfg(target_os = "linux")]
+mod activity;
+mod aggregation;
+mod backend;
+mod backup;
+mod crypto;
 mod db;
+mod export;
+mod goals;
+mod heatmap;
+mod ics;
+mod import;
 mod http_server;
+mod merge;
+mod notify;
+mod planner;
+mod report;
+mod schedule;
+mod search;
+mod streaks;
+mod sync;
+mod tracker;
 
 use db::Database;
 use parking_lot::Mutex;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::sync::Arc;
 use tauri::{
     CustomMenuItem, Manager, SystemTray, SystemTrayEvent, SystemTrayMenu, SystemTrayMenuItem,
 };
+use chrono::Timelike;
 use std::time::Duration;
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -18,6 +40,45 @@ pub struct Course {
     pub name: String,
     pub subject: String,
     pub url_pattern: String,
+    #[serde(default)]
+    pub app_pattern: String,
+    #[serde(default)]
+    pub title_pattern: String,
+    #[serde(default)]
+    pub subject_id: String,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Subject {
+    pub id: String,
+    pub name: String,
+    pub color: String,
+    pub parent_id: Option<String>,
+    pub sort_order: i64,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Tag {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TagStat {
+    pub id: String,
+    pub name: String,
+    pub duration: i64,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SubjectStat {
+    pub id: String,
+    pub name: String,
+    pub color: String,
+    pub parent_id: Option<String>,
+    pub sort_order: i64,
+    pub duration: i64,
+    pub total_duration: i64,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -28,122 +89,963 @@ pub struct StudyLog {
     pub duration: i64,
 }
 
-#[derive(Clone, Serialize, Deserialize)]
-pub struct CurrentSession {
-    pub course_name: String,
-    pub duration: i64,
+#[derive(Clone, Serialize, Deserialize)]
+pub struct StudySession {
+    pub id: String,
+    pub course_id: String,
+    pub chapter_id: Option<String>,
+    pub date: String,
+    pub start_time: i64,
+    pub end_time: i64,
+    pub duration: i64,
+    #[serde(default)]
+    pub note: String,
+    #[serde(default)]
+    pub focus: Option<i64>,
+    #[serde(default)]
+    pub titles: String,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ExportLog {
+    pub date: String,
+    pub course_id: String,
+    pub course_name: String,
+    pub subject: String,
+    pub duration: i64,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ExportSession {
+    pub id: String,
+    pub date: String,
+    pub start_time: i64,
+    pub end_time: i64,
+    pub course_id: String,
+    pub course_name: String,
+    pub subject: String,
+    pub chapter_name: Option<String>,
+    pub duration: i64,
+    pub focus: Option<i64>,
+    pub tags: Vec<String>,
+    pub note: String,
+}
+
+// 导入时的列映射，值为 CSV 表头名称（不区分大小写）
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ColumnMapping {
+    pub date: String,
+    pub start_time: Option<String>,
+    pub end_time: Option<String>,
+    pub duration: Option<String>,
+    pub duration_unit: Option<String>,
+    pub project: String,
+    pub subject: Option<String>,
+    pub description: Option<String>,
+    pub date_format: Option<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ImportRow {
+    pub line: usize,
+    pub date: String,
+    pub start_time: Option<i64>,
+    pub duration: i64,
+    pub project: String,
+    pub subject: String,
+    pub description: String,
+    pub course_id: Option<String>,
+    pub existing: i64,
+    pub duplicate: bool,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ImportPreview {
+    pub rows: Vec<ImportRow>,
+    pub errors: Vec<String>,
+    pub new_courses: Vec<String>,
+    pub duplicates: usize,
+    pub duration: i64,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ImportResult {
+    pub imported: usize,
+    pub skipped: usize,
+    pub created_courses: Vec<String>,
+    pub duration: i64,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SearchHit {
+    pub kind: String,
+    pub id: String,
+    pub title: String,
+    pub snippet: String,
+    pub course_id: String,
+    pub course_name: String,
+    pub date: Option<String>,
+    pub duration: i64,
+    pub score: f64,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SessionNote {
+    pub note: String,
+    pub focus: Option<i64>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CurrentSession {
+    pub course_name: String,
+    pub chapter_name: Option<String>,
+    pub duration: i64,
+    pub tags: Vec<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CourseStat {
+    pub course_id: String,
+    pub course_name: String,
+    pub subject: String,
+    pub duration: i64,
+    pub percent: f64,
+    pub completion: f64,
+    pub chapters: Vec<ChapterStat>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Chapter {
+    pub id: String,
+    pub course_id: String,
+    pub name: String,
+    pub sort_order: i64,
+    pub estimated_seconds: i64,
+    pub completed: bool,
+    pub url_pattern: String,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ChapterStat {
+    pub chapter_id: String,
+    pub name: String,
+    pub duration: i64,
+    pub estimated_seconds: i64,
+    pub completed: bool,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct DailyStat {
+    pub date: String,
+    pub duration: i64,
+    pub goal: i64,
+    pub goal_met: bool,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SubjectDuration {
+    pub subject: String,
+    pub duration: i64,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PeriodStat {
+    pub period: String,
+    pub start_date: String,
+    pub end_date: String,
+    pub duration: i64,
+    pub subjects: Vec<SubjectDuration>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct GoalSnapshot {
+    pub effective_date: String,
+    pub seconds: i64,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Goal {
+    pub id: String,
+    pub scope: String,
+    pub target: String,
+    pub period: String,
+    pub seconds: i64,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct GoalProgress {
+    pub goal_id: String,
+    pub scope: String,
+    pub target: String,
+    pub label: String,
+    pub period: String,
+    pub period_start: String,
+    pub period_end: String,
+    pub goal: i64,
+    pub studied: i64,
+    pub met: bool,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Statistics {
+    pub subjects: Vec<SubjectStat>,
+    pub tags: Vec<TagStat>,
+    pub course_stats: Vec<CourseStat>,
+    pub daily_stats: Vec<DailyStat>,
+    pub buckets: Vec<PeriodStat>,
+    pub goal_progress: Vec<GoalProgress>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SubjectHeatmap {
+    pub subject: String,
+    pub grid: Vec<Vec<i64>>,
+}
+
+// grid[星期][小时] 的学习秒数，星期 0 = 周一
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TimeHeatmap {
+    pub grid: Vec<Vec<i64>>,
+    pub subjects: Vec<SubjectHeatmap>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Streaks {
+    pub current_streak: i64,
+    pub longest_streak: i64,
+    pub days_studied_7: i64,
+    pub days_studied_30: i64,
+    pub average_per_studied_day: i64,
+    pub today_studied: i64,
+    pub today_goal: i64,
+    pub today_goal_met: bool,
+    pub at_risk: bool,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Exam {
+    pub id: String,
+    pub name: String,
+    pub date: String,
+    pub subjects: Vec<String>,
+    pub course_ids: Vec<String>,
+    pub target_seconds: i64,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ReportSubject {
+    pub name: String,
+    pub color: String,
+    pub duration: i64,
+    pub previous_duration: i64,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Report {
+    pub period: String,
+    pub start_date: String,
+    pub end_date: String,
+    pub previous_start: String,
+    pub previous_end: String,
+    pub total: i64,
+    pub previous_total: i64,
+    pub days: i64,
+    pub studied_days: i64,
+    pub goal_met_days: i64,
+    pub subjects: Vec<ReportSubject>,
+    pub course_stats: Vec<CourseStat>,
+    pub goal_progress: Vec<GoalProgress>,
+    pub streaks: Streaks,
+    pub exams: Vec<ExamCountdown>,
+}
+
+// 同步方式及其配置：backend 为 http（cloud-api 服务器）、webdav 或 folder（本地 / 共享文件夹）
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SyncConfig {
+    pub backend: String,
+    pub sync_url: String,
+    pub user_id: String,
+    pub folder: String,
+    pub webdav_url: String,
+    pub webdav_username: String,
+    // 密码不返回给界面，留空表示保持已保存的密码
+    #[serde(default)]
+    pub webdav_password: String,
+    #[serde(default)]
+    pub has_webdav_password: bool,
+    // 同步令牌同样不返回，留空表示保持不变
+    #[serde(default)]
+    pub sync_token: String,
+    #[serde(default)]
+    pub has_sync_token: bool,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SyncStatus {
+    pub configured: bool,
+    pub enabled: bool,
+    pub interval: i64,
+    pub running: bool,
+    pub last_success: Option<i64>,
+    pub last_failure: Option<i64>,
+    pub last_error: Option<String>,
+    pub failures: u32,
+    pub next_sync: Option<i64>,
+    pub conflicts: i64,
+}
+
+// 同步冲突：kept 为合并后保留的一方（local / remote），local 和 remote 为两个版本的内容
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SyncConflict {
+    pub id: i64,
+    pub table_name: String,
+    pub row_id: String,
+    pub label: String,
+    pub local: serde_json::Value,
+    pub remote: serde_json::Value,
+    pub kept: String,
+    pub detected_at: i64,
+}
+
+// 合并远端数据的结果：新建 / 更新的课程名，写入的每日记录和会话数（duration 为每日记录增加的时长），
+// 更新的设置项，删除的记录数和新产生的冲突数；拉取前的预览也使用这个结构
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SyncPreview {
+    pub new_courses: Vec<String>,
+    pub updated_courses: Vec<String>,
+    pub study_logs: usize,
+    pub sessions: usize,
+    pub duration: i64,
+    pub settings: Vec<String>,
+    pub deleted: usize,
+    pub conflicts: usize,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ExamCountdown {
+    pub subject: String,
+    pub exam_id: String,
+    pub exam_name: String,
+    pub date: String,
+    pub days_left: i64,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PlannedBlock {
+    pub id: String,
+    pub course_id: String,
+    pub weekday: Option<u32>,
+    pub date: Option<String>,
+    pub start_time: String,
+    pub end_time: String,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct AdherenceItem {
+    pub block_id: String,
+    pub course_id: String,
+    pub course_name: String,
+    pub date: String,
+    pub start_time: String,
+    pub end_time: String,
+    pub planned: i64,
+    pub actual: i64,
+    pub status: String,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct AdherenceReport {
+    pub items: Vec<AdherenceItem>,
+    pub hit: i64,
+    pub partial: i64,
+    pub missed: i64,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SubjectPlan {
+    pub subject: String,
+    pub exam_date: Option<String>,
+    pub target: i64,
+    pub studied: i64,
+    pub remaining: i64,
+    pub days_left: Option<i64>,
+    pub required_daily: i64,
+    pub recent_daily: i64,
+    pub projected_completion: Option<String>,
+    pub on_track: bool,
+    pub warning: Option<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ExamPlan {
+    pub exam_date: Option<String>,
+    pub days_left: Option<i64>,
+    pub subjects: Vec<SubjectPlan>,
+    pub exams: Vec<SubjectPlan>,
+    pub warnings: Vec<String>,
+}
+
+pub struct AppState {
+    pub db: Database,
+    pub current_course_id: Option<String>,
+    pub current_chapter_id: Option<String>,
+    pub session_start: Option<i64>,
+    // 当前章节分段的开始时间，切换章节时重置，session_start 不变
+    pub segment_start: Option<i64>,
+    pub last_report_time: Option<i64>,
+    // 当前会话的标签，记录会话时一并写入
+    pub current_tags: Vec<String>,
+    // 学习中添加的备注，写入下一段记录的会话
+    pub pending_note: Option<SessionNote>,
+    // 当前分段访问过的页面 / 窗口标题
+    pub current_titles: Vec<String>,
+}
+
+impl AppState {
+    pub fn new(db: Database) -> Self {
+        Self {
+            db,
+            current_course_id: None,
+            current_chapter_id: None,
+            session_start: None,
+            segment_start: None,
+            last_report_time: None,
+            current_tags: Vec::new(),
+            pending_note: None,
+            current_titles: Vec::new(),
+        }
+    }
+}
+
+type SharedState = Arc<Mutex<AppState>>;
+
+#[tauri::command]
+fn get_courses(state: tauri::State<SharedState>) -> Vec<Course> {
+    state.lock().db.get_courses()
+}
+
+#[tauri::command]
+#[allow(non_snake_case)]
+fn add_course(
+    state: tauri::State<SharedState>,
+    name: String,
+    subjectId: String,
+    urlPattern: String,
+    appPattern: Option<String>,
+    titlePattern: Option<String>,
+) -> Result<String, String> {
+    state.lock().db.add_course(
+        &name,
+        &subjectId,
+        &urlPattern,
+        &appPattern.unwrap_or_default(),
+        &titlePattern.unwrap_or_default(),
+    )
+}
+
+#[tauri::command]
+#[allow(non_snake_case)]
+fn update_course(
+    state: tauri::State<SharedState>,
+    id: String,
+    name: String,
+    subjectId: String,
+    urlPattern: String,
+    appPattern: Option<String>,
+    titlePattern: Option<String>,
+) -> Result<(), String> {
+    state.lock().db.update_course(
+        &id,
+        &name,
+        &subjectId,
+        &urlPattern,
+        &appPattern.unwrap_or_default(),
+        &titlePattern.unwrap_or_default(),
+    )
+}
+
+#[tauri::command]
+fn delete_course(state: tauri::State<SharedState>, id: String) {
+    state.lock().db.delete_course(&id);
+}
+
+#[tauri::command]
+fn get_subjects(state: tauri::State<SharedState>) -> Vec<Subject> {
+    state.lock().db.get_subjects()
+}
+
+#[tauri::command]
+#[allow(non_snake_case)]
+fn add_subject(state: tauri::State<SharedState>, name: String, color: Option<String>, parentId: Option<String>) -> Result<(), String> {
+    if name.trim().is_empty() {
+        return Err("科目名称不能为空".to_string());
+    }
+    state.lock().db.add_subject(name.trim(), &color.unwrap_or_default(), parentId.as_deref())
+}
+
+#[tauri::command]
+#[allow(non_snake_case)]
+fn update_subject(
+    state: tauri::State<SharedState>,
+    id: String,
+    name: String,
+    color: Option<String>,
+    parentId: Option<String>,
+    sortOrder: i64,
+) -> Result<(), String> {
+    if name.trim().is_empty() {
+        return Err("科目名称不能为空".to_string());
+    }
+    state.lock().db.update_subject(&id, name.trim(), &color.unwrap_or_default(), parentId.as_deref(), sortOrder)
+}
+
+#[tauri::command]
+fn delete_subject(state: tauri::State<SharedState>, id: String) -> Result<(), String> {
+    state.lock().db.delete_subject(&id)
+}
+
+#[tauri::command]
+fn get_tags(state: tauri::State<SharedState>) -> Vec<Tag> {
+    state.lock().db.get_tags()
+}
+
+#[tauri::command]
+fn add_tag(state: tauri::State<SharedState>, name: String) -> Result<Tag, String> {
+    if name.trim().is_empty() {
+        return Err("标签名称不能为空".to_string());
+    }
+    state.lock().db.add_tag(name.trim())
+}
+
+#[tauri::command]
+fn delete_tag(state: tauri::State<SharedState>, id: String) {
+    let mut s = state.lock();
+    s.db.delete_tag(&id);
+    s.current_tags.retain(|t| *t != id);
+}
+
+#[tauri::command]
+#[allow(non_snake_case)]
+fn get_course_tags(state: tauri::State<SharedState>, courseId: String) -> Vec<String> {
+    state.lock().db.get_course_tags(&courseId)
+}
+
+#[tauri::command]
+#[allow(non_snake_case)]
+fn set_course_tags(state: tauri::State<SharedState>, courseId: String, tagIds: Vec<String>) {
+    state.lock().db.set_course_tags(&courseId, &tagIds);
+}
+
+#[tauri::command]
+#[allow(non_snake_case)]
+fn get_session_tags(state: tauri::State<SharedState>, sessionId: String) -> Vec<String> {
+    state.lock().db.get_session_tags(&sessionId)
+}
+
+#[tauri::command]
+#[allow(non_snake_case)]
+fn set_session_tags(state: tauri::State<SharedState>, sessionId: String, tagIds: Vec<String>) -> Result<(), String> {
+    state.lock().db.set_session_tags(&sessionId, &tagIds)
+}
+
+// 给正在进行的会话打标签，会话结束（或按章节分段）记录时写入
+#[tauri::command]
+#[allow(non_snake_case)]
+fn tag_current_session(state: tauri::State<SharedState>, tagIds: Vec<String>) -> Result<(), String> {
+    let mut s = state.lock();
+    if s.session_start.is_none() {
+        return Err("当前没有进行中的学习".to_string());
+    }
+    s.current_tags = tagIds;
+    Ok(())
+}
+
+#[tauri::command]
+#[allow(non_snake_case)]
+fn get_chapters(state: tauri::State<SharedState>, courseId: String) -> Vec<Chapter> {
+    state.lock().db.get_chapters(&courseId)
+}
+
+#[tauri::command]
+#[allow(non_snake_case)]
+fn add_chapter(state: tauri::State<SharedState>, courseId: String, name: String, estimatedSeconds: i64, urlPattern: Option<String>) {
+    state.lock().db.add_chapter(&courseId, &name, estimatedSeconds, &urlPattern.unwrap_or_default());
+}
+
+#[tauri::command]
+#[allow(non_snake_case)]
+fn update_chapter(
+    state: tauri::State<SharedState>,
+    id: String,
+    name: String,
+    estimatedSeconds: i64,
+    urlPattern: Option<String>,
+    completed: bool,
+) {
+    state.lock().db.update_chapter(&id, &name, estimatedSeconds, &urlPattern.unwrap_or_default(), completed);
+}
+
+#[tauri::command]
+fn set_chapter_completed(state: tauri::State<SharedState>, id: String, completed: bool) {
+    state.lock().db.set_chapter_completed(&id, completed);
+}
+
+#[tauri::command]
+#[allow(non_snake_case)]
+fn reorder_chapters(state: tauri::State<SharedState>, courseId: String, ids: Vec<String>) {
+    state.lock().db.reorder_chapters(&courseId, &ids);
+}
+
+#[tauri::command]
+fn delete_chapter(state: tauri::State<SharedState>, id: String) {
+    state.lock().db.delete_chapter(&id);
+}
+
+#[tauri::command]
+fn get_daily_goal(state: tauri::State<SharedState>) -> i64 {
+    state.lock().db.get_daily_goal()
+}
+
+#[tauri::command]
+fn set_daily_goal(state: tauri::State<SharedState>, seconds: i64) {
+    state.lock().db.set_daily_goal(seconds);
+}
+
+#[tauri::command]
+fn get_goal_history(state: tauri::State<SharedState>) -> Vec<GoalSnapshot> {
+    state.lock().db.get_goal_history()
+}
+
+#[tauri::command]
+fn get_goals(state: tauri::State<SharedState>) -> Vec<Goal> {
+    state.lock().db.get_goals()
+}
+
+#[tauri::command]
+fn add_goal(state: tauri::State<SharedState>, scope: String, target: String, period: String, seconds: i64) -> Result<(), String> {
+    if !goals::is_valid_scope(&scope) || !goals::is_valid_period(&period) {
+        return Err("无效的目标范围或周期".to_string());
+    }
+    state.lock().db.add_goal(&scope, &target, &period, seconds);
+    Ok(())
+}
+
+#[tauri::command]
+fn update_goal(state: tauri::State<SharedState>, id: String, scope: String, target: String, period: String, seconds: i64) -> Result<(), String> {
+    if !goals::is_valid_scope(&scope) || !goals::is_valid_period(&period) {
+        return Err("无效的目标范围或周期".to_string());
+    }
+    state.lock().db.update_goal(&id, &scope, &target, &period, seconds);
+    Ok(())
+}
+
+#[tauri::command]
+fn delete_goal(state: tauri::State<SharedState>, id: String) {
+    state.lock().db.delete_goal(&id);
+}
+
+#[tauri::command]
+fn get_goal_progress(state: tauri::State<SharedState>) -> Vec<GoalProgress> {
+    let today = chrono::Local::now().date_naive();
+    state.lock().db.get_goal_progress(today)
+}
+
+#[tauri::command]
+fn get_today_studied(state: tauri::State<SharedState>) -> i64 {
+    let today = chrono::Local::now().format("%Y-%m-%d").to_string();
+    state.lock().db.get_studied_duration(&today)
+}
+
+#[tauri::command]
+fn get_current_session(state: tauri::State<SharedState>) -> Option<CurrentSession> {
+    let s = state.lock();
+    if let (Some(course_id), Some(start)) = (&s.current_course_id, s.session_start) {
+        let now = chrono::Utc::now().timestamp();
+        let duration = now - start;
+        if let Some(course) = s.db.get_course(course_id) {
+            let chapter_name = s.current_chapter_id.as_ref()
+                .and_then(|id| s.db.get_chapters(course_id).into_iter().find(|c| &c.id == id))
+                .map(|c| c.name);
+            return Some(CurrentSession {
+                course_name: course.name,
+                chapter_name,
+                duration,
+                tags: s.current_tags.clone(),
+            });
+        }
+    }
+    None
+}
+
+#[tauri::command]
+fn get_statistics(
+    state: tauri::State<SharedState>,
+    start_date: Option<String>,
+    end_date: Option<String>,
+    subject_id: Option<String>,
+    tag: Option<String>,
+    granularity: Option<String>,
+) -> Statistics {
+    state.lock().db.get_statistics(start_date, end_date, subject_id, tag, granularity)
+}
+
+// 导出 CSV 到用户选择的路径，kind 为 logs（每日记录）或 sessions（会话明细）
+#[tauri::command]
+fn export_csv(
+    state: tauri::State<SharedState>,
+    path: String,
+    kind: String,
+    start_date: Option<String>,
+    end_date: Option<String>,
+    subject_id: Option<String>,
+    tag: Option<String>,
+) -> Result<(), String> {
+    let csv = state.lock().db.export_csv(&kind, start_date, end_date, subject_id, tag)?;
+    std::fs::write(&path, csv).map_err(|e| format!("写入文件失败：{}", e))
+}
+
+// 导出 iCalendar 日历文件，可导入到日历应用
+#[tauri::command]
+fn export_ics(
+    state: tauri::State<SharedState>,
+    path: String,
+    start_date: Option<String>,
+    end_date: Option<String>,
+) -> Result<(), String> {
+    let calendar = state.lock().db.export_ics(start_date, end_date);
+    std::fs::write(&path, calendar).map_err(|e| format!("写入文件失败：{}", e))
+}
+
+// 日历订阅链接，带本机的订阅令牌
+#[tauri::command]
+fn get_calendar_url(state: tauri::State<SharedState>) -> String {
+    format!("http://127.0.0.1:23333/calendar.ics?token={}", state.lock().db.calendar_token())
+}
+
+// 导入预览：不写入数据，标出重复记录和需要新建的课程
+// courseMap 为 项目名 → 课程 id，未指定的项目按课程名称匹配
+#[tauri::command]
+#[allow(non_snake_case)]
+fn preview_import(
+    state: tauri::State<SharedState>,
+    path: String,
+    format: String,
+    mapping: Option<ColumnMapping>,
+    courseMap: Option<HashMap<String, String>>,
+) -> Result<ImportPreview, String> {
+    let (rows, errors) = import::read_file(&path, &format, mapping)?;
+    Ok(state.lock().db.preview_import(rows, errors, &courseMap.unwrap_or_default()))
+}
+
+#[tauri::command]
+#[allow(non_snake_case)]
+fn commit_import(
+    state: tauri::State<SharedState>,
+    path: String,
+    format: String,
+    mapping: Option<ColumnMapping>,
+    courseMap: Option<HashMap<String, String>>,
+    skipDuplicates: Option<bool>,
+) -> Result<ImportResult, String> {
+    let (rows, _) = import::read_file(&path, &format, mapping)?;
+    state.lock().db.commit_import(rows, &courseMap.unwrap_or_default(), skipDuplicates.unwrap_or(true))
+}
+
+#[tauri::command]
+fn get_sessions(state: tauri::State<SharedState>, start_date: String, end_date: String) -> Vec<StudySession> {
+    state.lock().db.get_sessions(&start_date, &end_date)
+}
+
+// 给正在进行的会话添加备注；没有进行中的会话时备注最近一次会话
+#[tauri::command]
+#[allow(non_snake_case)]
+fn annotate_session(state: tauri::State<SharedState>, sessionId: Option<String>, note: String, focus: Option<i64>) -> Result<(), String> {
+    tracker::annotate_session(&mut state.lock(), sessionId.as_deref(), note, focus)
+}
+
+#[tauri::command]
+fn search(state: tauri::State<SharedState>, query: String, limit: Option<i64>) -> Vec<SearchHit> {
+    state.lock().db.search(&query, limit.unwrap_or(50))
+}
+
+#[tauri::command]
+fn search_session_notes(state: tauri::State<SharedState>, query: String) -> Vec<StudySession> {
+    state.lock().db.search_session_notes(&query)
+}
+
+#[tauri::command]
+fn get_time_heatmap(
+    state: tauri::State<SharedState>,
+    start_date: Option<String>,
+    end_date: Option<String>,
+    subject_id: Option<String>,
+) -> TimeHeatmap {
+    state.lock().db.get_time_heatmap(start_date, end_date, subject_id)
+}
+
+#[tauri::command]
+fn get_streaks(state: tauri::State<SharedState>) -> Streaks {
+    let today = chrono::Local::now().date_naive();
+    state.lock().db.get_streaks(today)
 }
 
-#[derive(Clone, Serialize, Deserialize)]
-pub struct CourseStat {
-    pub course_id: String,
-    pub course_name: String,
-    pub subject: String,
-    pub duration: i64,
-    pub percent: f64,
+#[tauri::command]
+fn get_rest_days(state: tauri::State<SharedState>) -> Vec<u32> {
+    state.lock().db.get_setting("rest_days")
+        .map(|v| streaks::parse_rest_days(&v))
+        .unwrap_or_default()
 }
 
-#[derive(Clone, Serialize, Deserialize)]
-pub struct DailyStat {
-    pub date: String,
-    pub duration: i64,
-    pub goal_met: bool,
+#[tauri::command]
+fn set_rest_days(state: tauri::State<SharedState>, days: Vec<u32>) {
+    let value: Vec<String> = days.iter().filter(|d| **d < 7).map(|d| d.to_string()).collect();
+    state.lock().db.set_setting("rest_days", &value.join(","));
 }
 
-#[derive(Clone, Serialize, Deserialize)]
-pub struct Statistics {
-    pub subjects: Vec<String>,
-    pub course_stats: Vec<CourseStat>,
-    pub daily_stats: Vec<DailyStat>,
+#[tauri::command]
+fn get_exam_date(state: tauri::State<SharedState>) -> Option<String> {
+    state.lock().db.get_exam_date()
 }
 
-pub struct AppState {
-    pub db: Database,
-    pub current_course_id: Option<String>,
-    pub session_start: Option<i64>,
-    pub last_report_time: Option<i64>,
+#[tauri::command]
+fn set_exam_date(state: tauri::State<SharedState>, date: String) {
+    state.lock().db.set_exam_date(&date);
 }
 
-type SharedState = Arc<Mutex<AppState>>;
-
 #[tauri::command]
-fn get_courses(state: tauri::State<SharedState>) -> Vec<Course> {
-    state.lock().db.get_courses()
+fn get_exams(state: tauri::State<SharedState>) -> Vec<Exam> {
+    state.lock().db.get_exams()
 }
 
 #[tauri::command]
 #[allow(non_snake_case)]
-fn add_course(state: tauri::State<SharedState>, name: String, subject: String, urlPattern: String) {
-    state.lock().db.add_course(&name, &subject, &urlPattern);
+fn add_exam(
+    state: tauri::State<SharedState>,
+    name: String,
+    date: String,
+    subjects: Vec<String>,
+    courseIds: Vec<String>,
+    targetSeconds: i64,
+) {
+    state.lock().db.add_exam(&name, &date, &subjects, &courseIds, targetSeconds);
 }
 
 #[tauri::command]
 #[allow(non_snake_case)]
-fn update_course(state: tauri::State<SharedState>, id: String, name: String, subject: String, urlPattern: String) {
-    state.lock().db.update_course(&id, &name, &subject, &urlPattern);
+fn update_exam(
+    state: tauri::State<SharedState>,
+    id: String,
+    name: String,
+    date: String,
+    subjects: Vec<String>,
+    courseIds: Vec<String>,
+    targetSeconds: i64,
+) {
+    state.lock().db.update_exam(&id, &name, &date, &subjects, &courseIds, targetSeconds);
 }
 
 #[tauri::command]
-fn delete_course(state: tauri::State<SharedState>, id: String) {
-    state.lock().db.delete_course(&id);
+fn delete_exam(state: tauri::State<SharedState>, id: String) {
+    state.lock().db.delete_exam(&id);
 }
 
 #[tauri::command]
-fn get_daily_goal(state: tauri::State<SharedState>) -> i64 {
-    state.lock().db.get_daily_goal()
+fn get_exam_countdowns(state: tauri::State<SharedState>) -> Vec<ExamCountdown> {
+    let today = chrono::Local::now().date_naive();
+    state.lock().db.get_exam_countdowns(today)
 }
 
 #[tauri::command]
-fn set_daily_goal(state: tauri::State<SharedState>, seconds: i64) {
-    state.lock().db.set_daily_goal(seconds);
+fn get_exam_plan(state: tauri::State<SharedState>) -> ExamPlan {
+    let today = chrono::Local::now().date_naive();
+    state.lock().db.get_exam_plan(today)
 }
 
 #[tauri::command]
-fn get_today_studied(state: tauri::State<SharedState>) -> i64 {
-    let today = chrono::Local::now().format("%Y-%m-%d").to_string();
-    state.lock().db.get_studied_duration(&today)
+fn set_subject_target(state: tauri::State<SharedState>, subject: String, seconds: i64) {
+    state.lock().db.set_subject_target(&subject, seconds);
 }
 
 #[tauri::command]
-fn get_current_session(state: tauri::State<SharedState>) -> Option<CurrentSession> {
-    let s = state.lock();
-    if let (Some(course_id), Some(start)) = (&s.current_course_id, s.session_start) {
-        let now = chrono::Utc::now().timestamp();
-        let duration = now - start;
-        if let Some(course) = s.db.get_course(course_id) {
-            return Some(CurrentSession {
-                course_name: course.name,
-                duration,
-            });
-        }
+fn get_planned_blocks(state: tauri::State<SharedState>) -> Vec<PlannedBlock> {
+    state.lock().db.get_planned_blocks()
+}
+
+fn validate_block(weekday: Option<u32>, date: &Option<String>, start_time: &str, end_time: &str) -> Result<(), String> {
+    if weekday.is_none() == date.is_none() || weekday.map(|w| w > 6).unwrap_or(false) {
+        return Err("需要指定每周重复的星期或单次计划的日期".to_string());
+    }
+    match (schedule::parse_time(start_time), schedule::parse_time(end_time)) {
+        (Some(start), Some(end)) if start < end => Ok(()),
+        _ => Err("无效的时间段".to_string()),
     }
-    None
 }
 
 #[tauri::command]
-fn get_statistics(
+#[allow(non_snake_case)]
+fn add_planned_block(
     state: tauri::State<SharedState>,
-    start_date: Option<String>,
-    end_date: Option<String>,
-    subject: Option<String>,
-) -> Statistics {
-    state.lock().db.get_statistics(start_date, end_date, subject)
+    courseId: String,
+    weekday: Option<u32>,
+    date: Option<String>,
+    startTime: String,
+    endTime: String,
+) -> Result<(), String> {
+    validate_block(weekday, &date, &startTime, &endTime)?;
+    state.lock().db.add_planned_block(&courseId, weekday, date.as_deref(), &startTime, &endTime);
+    Ok(())
 }
 
 #[tauri::command]
-fn get_exam_date(state: tauri::State<SharedState>) -> Option<String> {
-    state.lock().db.get_exam_date()
+#[allow(non_snake_case)]
+fn update_planned_block(
+    state: tauri::State<SharedState>,
+    id: String,
+    courseId: String,
+    weekday: Option<u32>,
+    date: Option<String>,
+    startTime: String,
+    endTime: String,
+) -> Result<(), String> {
+    validate_block(weekday, &date, &startTime, &endTime)?;
+    state.lock().db.update_planned_block(&id, &courseId, weekday, date.as_deref(), &startTime, &endTime);
+    Ok(())
 }
 
 #[tauri::command]
-fn set_exam_date(state: tauri::State<SharedState>, date: String) {
-    state.lock().db.set_exam_date(&date);
+fn delete_planned_block(state: tauri::State<SharedState>, id: String) {
+    state.lock().db.delete_planned_block(&id);
+}
+
+#[tauri::command]
+fn get_adherence_report(state: tauri::State<SharedState>, start_date: String, end_date: String) -> Result<AdherenceReport, String> {
+    let parse = |d: &str| chrono::NaiveDate::parse_from_str(d, "%Y-%m-%d").map_err(|e| e.to_string());
+    let now = chrono::Utc::now().timestamp();
+    Ok(state.lock().db.get_adherence_report(parse(&start_date)?, parse(&end_date)?, now))
 }
 
+// 同步配置对应的设置项，密码和令牌以外的任一项变化都需要重新完整同步
+const SYNC_CONFIG_SETTINGS: [&str; 8] =
+    ["sync_backend", "sync_url", "user_id", "sync_folder", "webdav_url", "webdav_username", "webdav_password", "sync_token"];
+const SYNC_SECRETS: [&str; 2] = ["webdav_password", "sync_token"];
+
 #[tauri::command]
-fn get_sync_config(state: tauri::State<SharedState>) -> (Option<String>, Option<String>) {
+fn get_sync_config(state: tauri::State<SharedState>) -> SyncConfig {
     let s = state.lock();
-    (s.db.get_setting("sync_url"), s.db.get_setting("user_id"))
+    let [backend, sync_url, user_id, folder, webdav_url, webdav_username, webdav_password, sync_token] =
+        SYNC_CONFIG_SETTINGS.map(|key| s.db.get_setting(key).unwrap_or_default());
+    SyncConfig {
+        backend: if backend.is_empty() { "http".to_string() } else { backend },
+        sync_url,
+        user_id,
+        folder,
+        webdav_url,
+        webdav_username,
+        webdav_password: String::new(),
+        has_webdav_password: !webdav_password.is_empty(),
+        sync_token: String::new(),
+        has_sync_token: !sync_token.is_empty(),
+    }
 }
 
 #[tauri::command]
@@ -168,6 +1070,279 @@ fn set_auto_sync_config(state: tauri::State<SharedState>, autoSyncEnabled: bool,
     s.db.set_setting("auto_sync_enabled", if autoSyncEnabled { "true" } else { "false" });
     s.db.set_setting("auto_sync_interval", &autoSyncInterval.to_string());
     s.db.set_setting("sync_on_pause", if syncOnPause { "true" } else { "false" });
+    sync::wake();
+}
+
+// 立即同步，在后台线程执行网络请求，不阻塞界面
+#[tauri::command]
+async fn sync_now(state: tauri::State<'_, SharedState>) -> Result<String, String> {
+    let state = state.inner().clone();
+    tauri::async_runtime::spawn_blocking(move || sync::run_once(&state))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+#[tauri::command]
+fn get_sync_status(state: tauri::State<SharedState>) -> SyncStatus {
+    sync::status(&state.lock().db)
+}
+
+// 从服务器获取完整数据并预览合并结果，确认后由 apply_pull 写入
+#[tauri::command]
+async fn preview_pull(state: tauri::State<'_, SharedState>) -> Result<SyncPreview, String> {
+    let state = state.inner().clone();
+    tauri::async_runtime::spawn_blocking(move || sync::preview_pull(&state))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+// 写入预览过的远端数据，写入前先备份；返回合并结果和备份文件路径
+#[tauri::command]
+fn apply_pull(state: tauri::State<SharedState>) -> Result<(SyncPreview, String), String> {
+    let s = state.lock();
+    let dir = std::path::Path::new(backup::BACKUP_DIR);
+    std::fs::create_dir_all(dir).map_err(|e| format!("创建备份目录失败：{}", e))?;
+    let path = dir.join(backup::pull_file_name(chrono::Local::now()));
+    let result = sync::apply_pull(&s.db, || write_backup(&s.db, &path))?;
+    Ok((result, path.to_string_lossy().to_string()))
+}
+
+#[tauri::command]
+fn get_sync_encryption(state: tauri::State<SharedState>) -> (bool, String) {
+    sync::encryption(&state.lock().db)
+}
+
+// 以下涉及 Argon2 密钥派生或网络请求，在后台线程执行
+#[tauri::command]
+async fn enable_sync_encryption(state: tauri::State<'_, SharedState>, passphrase: String) -> Result<(), String> {
+    let state = state.inner().clone();
+    tauri::async_runtime::spawn_blocking(move || sync::enable_encryption(&state, &passphrase))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+#[tauri::command]
+async fn change_sync_passphrase(state: tauri::State<'_, SharedState>, passphrase: String, rotate: bool) -> Result<(), String> {
+    let state = state.inner().clone();
+    tauri::async_runtime::spawn_blocking(move || sync::change_passphrase(&state, &passphrase, rotate))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+#[tauri::command]
+async fn recover_sync_key(state: tauri::State<'_, SharedState>, phrase: String, passphrase: String) -> Result<(), String> {
+    let state = state.inner().clone();
+    tauri::async_runtime::spawn_blocking(move || sync::recover_key(&state, &phrase, &passphrase))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+#[tauri::command]
+fn export_recovery_phrase(state: tauri::State<SharedState>) -> Result<String, String> {
+    sync::recovery_phrase(&state.lock().db)
+}
+
+#[tauri::command]
+async fn disable_sync_encryption(state: tauri::State<'_, SharedState>) -> Result<(), String> {
+    let state = state.inner().clone();
+    tauri::async_runtime::spawn_blocking(move || sync::disable_encryption(&state))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+#[tauri::command]
+fn get_sync_conflicts(state: tauri::State<SharedState>) -> Vec<SyncConflict> {
+    state.lock().db.get_sync_conflicts()
+}
+
+#[tauri::command]
+#[allow(non_snake_case)]
+fn resolve_sync_conflict(state: tauri::State<SharedState>, id: i64, useOther: bool) -> Result<(), String> {
+    state.lock().db.resolve_sync_conflict(id, useOther)?;
+    if useOther {
+        sync::request();
+    }
+    Ok(())
+}
+
+// 写入完整备份（JSON）
+fn write_backup(db: &Database, path: &std::path::Path) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(&db.export_backup()).map_err(|e| e.to_string())?;
+    std::fs::write(path, json).map_err(|e| format!("写入备份失败：{}", e))
+}
+
+// 在 backups 目录写入一份自动备份并轮换旧备份
+fn write_auto_backup(db: &Database) -> Result<String, String> {
+    let dir = std::path::Path::new(backup::BACKUP_DIR);
+    std::fs::create_dir_all(dir).map_err(|e| format!("创建备份目录失败：{}", e))?;
+    let path = dir.join(backup::file_name(chrono::Local::now()));
+    write_backup(db, &path)?;
+    let keep = db.get_setting("auto_backup_keep").and_then(|v| v.parse().ok()).unwrap_or(7);
+    backup::rotate(dir, keep);
+    db.set_setting("last_auto_backup", &chrono::Utc::now().timestamp().to_string());
+    Ok(path.to_string_lossy().to_string())
+}
+
+#[tauri::command]
+fn export_backup(state: tauri::State<SharedState>, path: String) -> Result<(), String> {
+    write_backup(&state.lock().db, std::path::Path::new(&path))
+}
+
+// 恢复前先自动备份当前数据，mode 为 merge 或 replace
+#[tauri::command]
+fn import_backup(state: tauri::State<SharedState>, path: String, mode: String) -> Result<usize, String> {
+    if !backup::is_valid_mode(&mode) {
+        return Err("无效的恢复方式".to_string());
+    }
+    let content = std::fs::read_to_string(&path).map_err(|e| format!("读取备份失败：{}", e))?;
+    let data: serde_json::Value = serde_json::from_str(&content).map_err(|_| "不是有效的备份文件".to_string())?;
+    let s = state.lock();
+    write_auto_backup(&s.db)?;
+    s.db.import_backup(&data, &mode)
+}
+
+#[tauri::command]
+fn backup_now(state: tauri::State<SharedState>) -> Result<String, String> {
+    write_auto_backup(&state.lock().db)
+}
+
+#[tauri::command]
+fn get_backups() -> Vec<String> {
+    backup::list(std::path::Path::new(backup::BACKUP_DIR))
+        .into_iter()
+        .map(|p| p.to_string_lossy().to_string())
+        .collect()
+}
+
+#[tauri::command]
+fn get_backup_config(state: tauri::State<SharedState>) -> (bool, i64, i64) {
+    let s = state.lock();
+    let enabled = s.db.get_setting("auto_backup_enabled")
+        .map(|v| v == "true")
+        .unwrap_or(true);
+    let interval_days = s.db.get_setting("auto_backup_interval_days")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(1);
+    let keep = s.db.get_setting("auto_backup_keep")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(7);
+    (enabled, interval_days, keep)
+}
+
+#[tauri::command]
+#[allow(non_snake_case)]
+fn set_backup_config(state: tauri::State<SharedState>, enabled: bool, intervalDays: i64, keep: i64) {
+    let s = state.lock();
+    s.db.set_setting("auto_backup_enabled", if enabled { "true" } else { "false" });
+    s.db.set_setting("auto_backup_interval_days", &intervalDays.max(1).to_string());
+    s.db.set_setting("auto_backup_keep", &keep.max(1).to_string());
+}
+
+// 生成学习报告，date 为周期内任意一天，默认今天
+fn build_report(db: &Database, period: &str, date: Option<&str>, format: &str) -> Result<(Report, String), String> {
+    if !report::is_valid_format(format) {
+        return Err("无效的报告格式".to_string());
+    }
+    let date = match date.filter(|d| !d.is_empty()) {
+        Some(d) => chrono::NaiveDate::parse_from_str(d, "%Y-%m-%d").map_err(|_| "日期格式应为 YYYY-MM-DD".to_string())?,
+        None => chrono::Local::now().date_naive(),
+    };
+    let data = db.get_report(period, date)?;
+    let content = report::render(&data, format)?;
+    Ok((data, content))
+}
+
+// 在 reports 目录写入上周的周报，返回文件路径
+fn write_weekly_report(db: &Database) -> Result<String, String> {
+    let format = db.get_setting("weekly_report_format").unwrap_or_else(|| "html".to_string());
+    let last_week = (chrono::Local::now() - chrono::Duration::days(7)).format("%Y-%m-%d").to_string();
+    let (data, content) = build_report(db, "weekly", Some(&last_week), &format)?;
+    let dir = std::path::Path::new(report::REPORT_DIR);
+    std::fs::create_dir_all(dir).map_err(|e| format!("创建报告目录失败：{}", e))?;
+    let path = dir.join(report::file_name(&data, &format));
+    std::fs::write(&path, content).map_err(|e| format!("写入报告失败：{}", e))?;
+    db.set_setting("last_weekly_report", &data.start_date);
+    Ok(path.to_string_lossy().to_string())
+}
+
+// 返回报告内容，period 为 weekly / monthly，format 为 html / markdown
+#[tauri::command]
+fn generate_report(state: tauri::State<SharedState>, period: String, date: Option<String>, format: String) -> Result<String, String> {
+    build_report(&state.lock().db, &period, date.as_deref(), &format).map(|(_, content)| content)
+}
+
+#[tauri::command]
+fn export_report(
+    state: tauri::State<SharedState>,
+    path: String,
+    period: String,
+    date: Option<String>,
+    format: String,
+) -> Result<(), String> {
+    let (_, content) = build_report(&state.lock().db, &period, date.as_deref(), &format)?;
+    std::fs::write(&path, content).map_err(|e| format!("写入文件失败：{}", e))
+}
+
+#[tauri::command]
+fn get_report_config(state: tauri::State<SharedState>) -> (bool, String) {
+    let s = state.lock();
+    let enabled = s.db.get_setting("weekly_report_enabled")
+        .map(|v| v == "true")
+        .unwrap_or(false);
+    let format = s.db.get_setting("weekly_report_format").unwrap_or_else(|| "html".to_string());
+    (enabled, format)
+}
+
+#[tauri::command]
+fn set_report_config(state: tauri::State<SharedState>, enabled: bool, format: String) -> Result<(), String> {
+    if !report::is_valid_format(&format) {
+        return Err("无效的报告格式".to_string());
+    }
+    let s = state.lock();
+    s.db.set_setting("weekly_report_enabled", if enabled { "true" } else { "false" });
+    s.db.set_setting("weekly_report_format", &format);
+    Ok(())
+}
+
+// 发布版使用 windows 子系统，没有控制台；命令行模式下附加到启动它的终端，输出才可见
+#[cfg(windows)]
+fn attach_parent_console() {
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn AttachConsole(process_id: u32) -> i32;
+    }
+    const ATTACH_PARENT_PROCESS: u32 = u32::MAX;
+    unsafe {
+        AttachConsole(ATTACH_PARENT_PROCESS);
+    }
+}
+
+// 命令行生成报告，不启动界面：
+// study-monitor report [weekly|monthly] [--date YYYY-MM-DD] [--format html|markdown] [--output 路径]
+fn run_report_cli(args: &[String]) -> Result<(), String> {
+    let mut period = "weekly".to_string();
+    let mut date = None;
+    let mut format = "markdown".to_string();
+    let mut output = None;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--date" => date = iter.next().cloned(),
+            "--format" => format = iter.next().cloned().unwrap_or_default(),
+            "--output" => output = iter.next().cloned(),
+            p if !p.starts_with("--") => period = p.to_string(),
+            other => return Err(format!("未知参数：{}", other)),
+        }
+    }
+    let db = Database::new().map_err(|e| e.to_string())?;
+    let (_, content) = build_report(&db, &period, date.as_deref(), &format)?;
+    match output {
+        Some(path) => std::fs::write(&path, content).map_err(|e| format!("写入文件失败：{}", e)),
+        None => {
+            print!("{}", content);
+            Ok(())
+        }
+    }
 }
 
 #[tauri::command]
@@ -182,6 +1357,18 @@ fn set_notifications_enabled(state: tauri::State<SharedState>, enabled: bool) {
     state.lock().db.set_setting("notifications_enabled", if enabled { "true" } else { "false" });
 }
 
+#[tauri::command]
+fn get_app_tracking_enabled(state: tauri::State<SharedState>) -> bool {
+    state.lock().db.get_setting("app_tracking_enabled")
+        .map(|v| v == "true")
+        .unwrap_or(true)
+}
+
+#[tauri::command]
+fn set_app_tracking_enabled(state: tauri::State<SharedState>, enabled: bool) {
+    state.lock().db.set_setting("app_tracking_enabled", if enabled { "true" } else { "false" });
+}
+
 #[tauri::command]
 fn get_auto_launch() -> bool {
     let auto = auto_launch::AutoLaunchBuilder::new()
@@ -209,45 +1396,75 @@ fn set_auto_launch(enabled: bool) -> Result<(), String> {
 }
 
 #[tauri::command]
-#[allow(non_snake_case)]
-fn set_sync_config(state: tauri::State<SharedState>, syncUrl: String, userId: String) {
+fn set_sync_config(state: tauri::State<SharedState>, config: SyncConfig) -> Result<(), String> {
+    if !backend::BACKENDS.contains(&config.backend.as_str()) {
+        return Err("无效的同步方式".to_string());
+    }
     let s = state.lock();
-    s.db.set_setting("sync_url", &syncUrl);
-    s.db.set_setting("user_id", &userId);
+    let secret = |key: &str, value: &str| match value {
+        "" => s.db.get_setting(key).unwrap_or_default(),
+        value => value.to_string(),
+    };
+    let password = secret("webdav_password", &config.webdav_password);
+    let token = secret("sync_token", config.sync_token.trim());
+    let values = [
+        &config.backend,
+        config.sync_url.trim(),
+        config.user_id.trim(),
+        config.folder.trim(),
+        config.webdav_url.trim(),
+        config.webdav_username.trim(),
+        &password,
+        &token,
+    ];
+    let changed = SYNC_CONFIG_SETTINGS
+        .iter()
+        .zip(values)
+        .any(|(key, value)| !SYNC_SECRETS.contains(key) && s.db.get_setting(key).unwrap_or_default() != value);
+    if changed {
+        sync::reset_cursors(&s.db);
+    }
+    for (key, value) in SYNC_CONFIG_SETTINGS.iter().zip(values) {
+        s.db.set_setting(key, value);
+    }
+    sync::wake();
+    Ok(())
 }
 
 #[tauri::command]
 fn get_sync_data(state: tauri::State<SharedState>) -> serde_json::Value {
-    let s = state.lock();
-    let courses = s.db.get_courses();
-    let study_logs = s.db.get_all_study_logs();
-    let daily_goal = s.db.get_daily_goal();
-    let exam_date = s.db.get_exam_date();
-    
-    serde_json::json!({
-        "courses": courses,
-        "studyLogs": study_logs,
-        "settings": {
-            "daily_goal": daily_goal,
-            "exam_date": exam_date.unwrap_or_default()
-        }
-    })
+    sync::payload(&state.lock().db)
 }
 
 fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(|a| a == "report").unwrap_or(false) {
+        #[cfg(windows)]
+        attach_parent_console();
+        if let Err(e) = run_report_cli(&args[2..]) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let db = Database::new().expect("Failed to initialize database");
-    let state = Arc::new(Mutex::new(AppState {
-        db,
-        current_course_id: None,
-        session_start: None,
-        last_report_time: None,
-    }));
+    let state = Arc::new(Mutex::new(AppState::new(db)));
 
     let http_state = state.clone();
     std::thread::spawn(move || {
         http_server::start_server(http_state);
     });
 
+    // 桌面应用监听线程（X11）
+    #[cfg(target_os = "linux")]
+    {
+        let activity_state = state.clone();
+        std::thread::spawn(move || {
+            activity::start_watcher(activity_state);
+        });
+    }
+
     // 超时检测线程：30秒没收到上报就自动暂停
     let timeout_state = state.clone();
     std::thread::spawn(move || {
@@ -257,14 +1474,13 @@ fn main() {
             let mut s = timeout_state.lock();
             
             if let (Some(course_id), Some(start), Some(last_report)) = 
-                (s.current_course_id.clone(), s.session_start, s.last_report_time) 
+                (s.current_course_id.clone(), s.segment_start, s.last_report_time) 
             {
                 // 超过30秒没上报
                 if now - last_report > 30 {
                     let today = chrono::Local::now().format("%Y-%m-%d").to_string();
-                    let duration = last_report - start;
-                    if duration > 0 {
-                        s.db.add_study_log(&course_id, &today, duration);
+                    if last_report > start {
+                        tracker::save_segment(&mut s, &course_id, &today, start, last_report);
                     }
                     
                     // 检查是否启用暂停时同步
@@ -272,12 +1488,17 @@ fn main() {
                         .map(|v| v == "true")
                         .unwrap_or(false);
                     if sync_on_pause {
-                        http_server::SYNC_TRIGGER.store(true, std::sync::atomic::Ordering::SeqCst);
+                        sync::request();
                     }
                     
                     s.current_course_id = None;
+                    s.current_chapter_id = None;
                     s.session_start = None;
+                    s.segment_start = None;
                     s.last_report_time = None;
+                    s.current_tags.clear();
+                    s.pending_note = None;
+                    s.current_titles.clear();
                     
                     println!("Session timeout - auto paused");
                 }
@@ -285,6 +1506,162 @@ fn main() {
         }
     });
 
+    // 同步线程：不依赖界面，窗口未打开时也按设置同步
+    let sync_state = state.clone();
+    std::thread::spawn(move || {
+        sync::start_engine(sync_state);
+    });
+
+    // 自动备份线程：启动时和之后每小时检查一次，距上次备份超过设定天数就备份
+    let backup_state = state.clone();
+    std::thread::spawn(move || loop {
+        {
+            let s = backup_state.lock();
+            let enabled = s.db.get_setting("auto_backup_enabled").map(|v| v == "true").unwrap_or(true);
+            let interval_days: i64 = s.db.get_setting("auto_backup_interval_days")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(1);
+            let last: i64 = s.db.get_setting("last_auto_backup")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(0);
+            if enabled && chrono::Utc::now().timestamp() - last >= interval_days * 86400 {
+                if let Err(e) = write_auto_backup(&s.db) {
+                    println!("Auto backup failed: {}", e);
+                }
+            }
+        }
+        std::thread::sleep(Duration::from_secs(3600));
+    });
+
+    // 周报线程：开启后每周一起生成上一周的周报，启动时和之后每小时检查一次
+    let report_state = state.clone();
+    std::thread::spawn(move || loop {
+        let written = {
+            let s = report_state.lock();
+            let enabled = s.db.get_setting("weekly_report_enabled").map(|v| v == "true").unwrap_or(false);
+            let today = chrono::Local::now().date_naive();
+            let (last_week_start, _) = goals::period_range("weekly", today - chrono::Duration::days(7));
+            let done = s.db.get_setting("last_weekly_report")
+                .map(|v| v >= last_week_start.format("%Y-%m-%d").to_string())
+                .unwrap_or(false);
+            if enabled && !done {
+                match write_weekly_report(&s.db) {
+                    Ok(path) => Some(path),
+                    Err(e) => {
+                        println!("Weekly report failed: {}", e);
+                        None
+                    }
+                }
+            } else {
+                None
+            }
+        };
+        if let Some(path) = written {
+            notify::send_notification("周报已生成", &path, &report_state);
+        }
+        std::thread::sleep(Duration::from_secs(3600));
+    });
+
+    // 连续学习提醒线程：晚上仍未达标时提醒一次
+    let streak_state = state.clone();
+    std::thread::spawn(move || {
+        let mut reminded_on: Option<chrono::NaiveDate> = None;
+        loop {
+            std::thread::sleep(Duration::from_secs(60));
+            let now = chrono::Local::now();
+            let today = now.date_naive();
+            let reminder_hour: u32 = streak_state.lock().db.get_setting("streak_reminder_hour")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(20);
+            if now.hour() < reminder_hour || reminded_on == Some(today) {
+                continue;
+            }
+
+            let streak = streak_state.lock().db.get_streaks(today);
+            if streak.at_risk {
+                let remaining = (streak.today_goal - streak.today_studied).max(0) / 60;
+                let msg = format!("已连续达标 {} 天，今天还差 {} 分钟", streak.current_streak, remaining);
+                notify::send_notification("连续学习即将中断", &msg, &streak_state);
+            }
+            reminded_on = Some(today);
+        }
+    });
+
+    // 学习计划提醒线程：计划开始时还没有在学对应课程就提醒
+    let schedule_state = state.clone();
+    std::thread::spawn(move || {
+        let mut reminded: Vec<(String, chrono::NaiveDate)> = Vec::new();
+        loop {
+            std::thread::sleep(Duration::from_secs(60));
+            let now = chrono::Utc::now().timestamp();
+            let today = chrono::Local::now().date_naive();
+            reminded.retain(|(_, date)| *date == today);
+
+            let (blocks, current_course_id) = {
+                let s = schedule_state.lock();
+                (s.db.get_planned_blocks(), s.current_course_id.clone())
+            };
+            for block in blocks.iter().filter(|b| schedule::occurs_on(b, today)) {
+                let Some((start, end)) = schedule::occurrence_range(block, today) else {
+                    continue;
+                };
+                // 只在开始后的几分钟内提醒，中途离开不再打扰
+                let started = now >= start && now < (start + 300).min(end);
+                let already = reminded.iter().any(|(id, _)| *id == block.id);
+                if !started || already || current_course_id.as_ref() == Some(&block.course_id) {
+                    continue;
+                }
+                reminded.push((block.id.clone(), today));
+
+                let course = schedule_state.lock().db.get_course(&block.course_id);
+                if let Some(course) = course {
+                    let msg = format!("{} {}-{}，还没有开始学习", course.name, block.start_time, block.end_time);
+                    notify::send_notification("计划学习时间到了", &msg, &schedule_state);
+                }
+            }
+        }
+    });
+
+    // 考试提醒线程：每天早上按各科目最近的考试提醒一次
+    let exam_state = state.clone();
+    std::thread::spawn(move || {
+        let mut reminded_on: Option<chrono::NaiveDate> = None;
+        loop {
+            std::thread::sleep(Duration::from_secs(60));
+            let now = chrono::Local::now();
+            let today = now.date_naive();
+            if now.hour() < 8 || reminded_on == Some(today) {
+                continue;
+            }
+            reminded_on = Some(today);
+
+            let (countdowns, plan) = {
+                let s = exam_state.lock();
+                (s.db.get_exam_countdowns(today), s.db.get_exam_plan(today))
+            };
+
+            // 同一场考试覆盖多个科目时合并成一条
+            let mut exams: Vec<(String, i64, Vec<String>)> = Vec::new();
+            for c in countdowns.into_iter().filter(|c| [0, 1, 3, 7].contains(&c.days_left)) {
+                match exams.iter_mut().find(|e| e.0 == c.exam_name) {
+                    Some(e) => e.2.push(c.subject),
+                    None => exams.push((c.exam_name, c.days_left, vec![c.subject])),
+                }
+            }
+            for (name, days_left, subjects) in exams {
+                let msg = if days_left == 0 {
+                    format!("{}（{}）今天考试", name, subjects.join("、"))
+                } else {
+                    format!("{}（{}）还有 {} 天", name, subjects.join("、"), days_left)
+                };
+                notify::send_notification("考试倒计时", &msg, &exam_state);
+            }
+            for warning in plan.warnings {
+                notify::send_notification("考前进度落后", &warning, &exam_state);
+            }
+        }
+    });
+
     // 系统托盘菜单
     let tray_menu = SystemTrayMenu::new()
         .add_item(CustomMenuItem::new("open", "打开"))
@@ -328,20 +1705,93 @@ fn main() {
             add_course,
             update_course,
             delete_course,
+            get_subjects,
+            add_subject,
+            update_subject,
+            delete_subject,
+            get_tags,
+            add_tag,
+            delete_tag,
+            get_course_tags,
+            set_course_tags,
+            get_session_tags,
+            set_session_tags,
+            tag_current_session,
+            get_chapters,
+            add_chapter,
+            update_chapter,
+            set_chapter_completed,
+            reorder_chapters,
+            delete_chapter,
             get_daily_goal,
             set_daily_goal,
+            get_goal_history,
+            get_goals,
+            add_goal,
+            update_goal,
+            delete_goal,
+            get_goal_progress,
             get_today_studied,
             get_current_session,
             get_statistics,
+            get_sessions,
+            export_csv,
+            export_ics,
+            get_calendar_url,
+            generate_report,
+            export_report,
+            get_report_config,
+            set_report_config,
+            preview_import,
+            commit_import,
+            annotate_session,
+            search_session_notes,
+            search,
+            get_time_heatmap,
+            get_streaks,
+            get_rest_days,
+            set_rest_days,
             get_exam_date,
             set_exam_date,
+            get_exams,
+            add_exam,
+            update_exam,
+            delete_exam,
+            get_exam_countdowns,
+            get_exam_plan,
+            set_subject_target,
+            get_planned_blocks,
+            add_planned_block,
+            update_planned_block,
+            delete_planned_block,
+            get_adherence_report,
             get_sync_config,
             set_sync_config,
             get_sync_data,
             get_auto_sync_config,
             set_auto_sync_config,
+            sync_now,
+            get_sync_status,
+            get_sync_conflicts,
+            preview_pull,
+            apply_pull,
+            get_sync_encryption,
+            enable_sync_encryption,
+            change_sync_passphrase,
+            recover_sync_key,
+            export_recovery_phrase,
+            disable_sync_encryption,
+            resolve_sync_conflict,
+            export_backup,
+            import_backup,
+            backup_now,
+            get_backups,
+            get_backup_config,
+            set_backup_config,
             get_notifications_enabled,
             set_notifications_enabled,
+            get_app_tracking_enabled,
+            set_app_tracking_enabled,
             get_auto_launch,
             set_auto_launch,
         ])
@@ -353,17 +1803,6 @@ fn main() {
                 if let Some(window) = _app_handle.get_window("main") {
                     window.show().unwrap();
                 }
-                
-                // 启动同步触发检查线程
-                let app_handle = _app_handle.clone();
-                std::thread::spawn(move || {
-                    loop {
-                        std::thread::sleep(Duration::from_secs(1));
-                        if http_server::should_sync() {
-                            let _ = app_handle.emit_all("sync-on-pause", ());
-                        }
-                    }
-                });
             }
         });
 }
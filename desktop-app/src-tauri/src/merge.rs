@@ -0,0 +1,219 @@
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, HashMap};
+
+// 多设备合并规则：按修改时间后写入者为准；修改时间相同时比较内容，
+// 服务器（cloud-api/server.js 中的 remoteWins）和各设备得到相同的结果
+
+// 同步的列，JSON 字段名与列名相同
+pub fn columns(table: &str) -> &'static [&'static str] {
+    match table {
+        "courses" => &["id", "name", "subject", "url_pattern", "updated_at"],
+        "study_logs" => &["id", "course_id", "date", "duration", "device_id", "updated_at"],
+        "sessions" => &[
+            "id", "course_id", "chapter_id", "date", "start_time", "end_time", "duration", "note", "focus", "titles",
+            "device_id", "updated_at",
+        ],
+        _ => &["key", "value", "updated_at"],
+    }
+}
+
+// 可以在多台设备上修改的内容，用于判断冲突和展示冲突的两个版本
+pub fn content_fields(table: &str) -> &'static [&'static str] {
+    match table {
+        "courses" => &["name", "subject", "url_pattern"],
+        "study_logs" => &["course_id", "date", "duration"],
+        "sessions" => &["note", "focus", "titles"],
+        _ => &["value"],
+    }
+}
+
+// 每日记录只由记录它的设备修改，设置项只有一个值，两者都直接按规则合并
+pub fn can_conflict(table: &str) -> bool {
+    matches!(table, "courses" | "sessions")
+}
+
+fn text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
+fn content_key(table: &str, row: &Value) -> String {
+    content_fields(table)
+        .iter()
+        .map(|f| text(&row[*f]))
+        .collect::<Vec<_>>()
+        .join("\u{1f}")
+}
+
+pub fn content(table: &str, row: &Value) -> Value {
+    Value::Object(content_fields(table).iter().map(|f| (f.to_string(), row[*f].clone())).collect())
+}
+
+pub fn differs(table: &str, local: &Value, remote: &Value) -> bool {
+    content_key(table, local) != content_key(table, remote)
+}
+
+pub fn remote_wins(table: &str, local: &Value, remote: &Value) -> bool {
+    let local_at = local["updated_at"].as_i64().unwrap_or(0);
+    let remote_at = remote["updated_at"].as_i64().unwrap_or(0);
+    local_at < remote_at || (local_at == remote_at && content_key(table, local) < content_key(table, remote))
+}
+
+// 变更数据中各表对应的字段
+const FIELDS: [(&str, &str); 3] = [("courses", "courses"), ("studyLogs", "study_logs"), ("sessions", "sessions")];
+
+// 去掉 changes 中与 known 里 id、修改时间和内容都相同的记录，
+// 用于文件同步时忽略其它设备转发回来的、本机上次导出的版本
+pub fn without_known(changes: &Value, known: &Value) -> Value {
+    let mut result = changes.clone();
+    for (field, table) in FIELDS {
+        let known: HashMap<String, &Value> = known[field]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|row| (text(&row["id"]), row))
+            .collect();
+        if let Some(rows) = result[field].as_array_mut() {
+            rows.retain(|row| {
+                !known.get(&text(&row["id"])).is_some_and(|k| {
+                    k["updated_at"].as_i64() == row["updated_at"].as_i64() && !differs(table, k, row)
+                })
+            });
+        }
+    }
+    result
+}
+
+// 合并多台设备的变更（文件同步时每台设备一个文件），同一记录只保留按规则胜出的版本；
+// 其它两台设备之间的冲突由它们各自记录，不在本机重复出现
+pub fn combine(all: &[Value]) -> Value {
+    let mut result = Map::new();
+    for (field, table) in FIELDS {
+        let mut rows: Vec<Value> = Vec::new();
+        let mut index = HashMap::new();
+        for row in all.iter().flat_map(|c| c[field].as_array().into_iter().flatten()) {
+            match index.get(&text(&row["id"])) {
+                Some(&i) if remote_wins(table, &rows[i], row) => rows[i] = row.clone(),
+                Some(_) => {}
+                None => {
+                    index.insert(text(&row["id"]), rows.len());
+                    rows.push(row.clone());
+                }
+            }
+        }
+        result.insert(field.to_string(), rows.into());
+    }
+
+    let mut values = Map::new();
+    let mut updated_at = Map::new();
+    for changes in all {
+        for (key, value) in changes["settings"].as_object().into_iter().flatten() {
+            let at = changes["settingsUpdatedAt"][key].clone();
+            let wins = match values.get(key) {
+                Some(current) => remote_wins(
+                    "settings",
+                    &json!({ "value": text(current), "updated_at": updated_at[key] }),
+                    &json!({ "value": text(value), "updated_at": at }),
+                ),
+                None => true,
+            };
+            if wins {
+                values.insert(key.clone(), value.clone());
+                updated_at.insert(key.clone(), at);
+            }
+        }
+    }
+    result.insert("settings".to_string(), values.into());
+    result.insert("settingsUpdatedAt".to_string(), updated_at.into());
+
+    // 删除记录按表和 id 保留最晚的删除时间
+    let mut deleted: BTreeMap<String, BTreeMap<String, i64>> = BTreeMap::new();
+    for changes in all {
+        for (field, list) in changes["deleted"].as_object().into_iter().flatten() {
+            let ids = deleted.entry(field.clone()).or_default();
+            for entry in list.as_array().into_iter().flatten() {
+                let at = entry["deleted_at"].as_i64().unwrap_or(0);
+                let latest = ids.entry(text(&entry["id"])).or_insert(at);
+                *latest = (*latest).max(at);
+            }
+        }
+    }
+    let deleted: Map<String, Value> = deleted
+        .into_iter()
+        .map(|(field, ids)| {
+            let list = ids.into_iter().map(|(id, at)| json!({ "id": id, "deleted_at": at })).collect::<Vec<_>>();
+            (field, list.into())
+        })
+        .collect();
+    result.insert("deleted".to_string(), deleted.into());
+    Value::Object(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn course(id: &str, name: &str, updated_at: i64) -> Value {
+        json!({ "id": id, "name": name, "subject": "数学", "url_pattern": "", "updated_at": updated_at })
+    }
+
+    // 与 server.js 中的 remoteWins 相同：后写入者为准，时间相同时内容较大的一方胜出
+    #[test]
+    fn remote_wins_by_updated_at_then_content() {
+        assert!(remote_wins("courses", &course("a", "高数", 1), &course("a", "线代", 2)));
+        assert!(!remote_wins("courses", &course("a", "线代", 2), &course("a", "高数", 1)));
+
+        let (low, high) = (course("a", "A", 5), course("a", "B", 5));
+        assert!(remote_wins("courses", &low, &high));
+        assert!(!remote_wins("courses", &high, &low));
+        // 内容相同时保留本地版本，避免来回覆盖
+        assert!(!remote_wins("courses", &low, &low.clone()));
+    }
+
+    #[test]
+    fn remote_wins_treats_missing_updated_at_as_zero() {
+        let legacy = json!({ "id": "a", "name": "高数", "subject": "数学", "url_pattern": "" });
+        assert!(remote_wins("courses", &legacy, &course("a", "高数", 1)));
+        assert!(!remote_wins("courses", &course("a", "高数", 1), &legacy));
+    }
+
+    #[test]
+    fn combine_keeps_the_winning_version_regardless_of_order() {
+        let a = json!({ "courses": [course("c1", "旧", 1), course("c2", "A", 3)], "studyLogs": [], "sessions": [] });
+        let b = json!({ "courses": [course("c1", "新", 2), course("c2", "B", 3)], "studyLogs": [], "sessions": [] });
+
+        for all in [[a.clone(), b.clone()], [b, a]] {
+            let combined = combine(&all);
+            let courses = combined["courses"].as_array().unwrap();
+            assert_eq!(courses.len(), 2);
+            let name = |id: &str| courses.iter().find(|c| c["id"] == id).unwrap()["name"].clone();
+            assert_eq!(name("c1"), "新");
+            assert_eq!(name("c2"), "B");
+        }
+    }
+
+    #[test]
+    fn combine_settings_by_updated_at() {
+        let a = json!({ "settings": { "daily_goal": "3600" }, "settingsUpdatedAt": { "daily_goal": 2 } });
+        let b = json!({ "settings": { "daily_goal": "7200", "theme": "dark" }, "settingsUpdatedAt": { "daily_goal": 1, "theme": 1 } });
+        let combined = combine(&[b, a]);
+        assert_eq!(combined["settings"]["daily_goal"], "3600");
+        assert_eq!(combined["settingsUpdatedAt"]["daily_goal"], 2);
+        assert_eq!(combined["settings"]["theme"], "dark");
+    }
+
+    #[test]
+    fn combine_keeps_latest_tombstone_across_devices() {
+        let a = json!({ "deleted": { "courses": [{ "id": "c1", "deleted_at": 5 }] } });
+        let b = json!({ "deleted": { "courses": [{ "id": "c1", "deleted_at": 9 }, { "id": "c2", "deleted_at": 3 }] } });
+        let c = json!({ "deleted": { "courses": [{ "id": "c1", "deleted_at": 7 }] } });
+        let combined = combine(&[a, b, c]);
+        assert_eq!(
+            combined["deleted"]["courses"],
+            json!([{ "id": "c1", "deleted_at": 9 }, { "id": "c2", "deleted_at": 3 }])
+        );
+    }
+}
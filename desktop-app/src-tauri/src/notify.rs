@@ -0,0 +1,20 @@
+use crate::SharedState;
+use winrt_notification::{Duration, Sound, Toast};
+
+pub fn send_notification(title: &str, body: &str, state: &SharedState) {
+    // 检查是否启用通知
+    let enabled = state.lock().db.get_setting("notifications_enabled")
+        .map(|v| v == "true")
+        .unwrap_or(true); // 默认开启
+    
+    if !enabled {
+        return;
+    }
+    
+    let _ = Toast::new(Toast::POWERSHELL_APP_ID)
+        .title(title)
+        .text1(body)
+        .duration(Duration::Short)
+        .sound(Some(Sound::Default))
+        .show();
+}
@@ -0,0 +1,66 @@
+use crate::SubjectPlan;
+use chrono::{Duration, NaiveDate};
+
+// 用最近多少天的学习速度推算完成日期
+pub const RATE_WINDOW_DAYS: i64 = 14;
+
+// 根据目标总时长、已学时长和最近的学习速度推算考前进度
+pub fn plan_subject(
+    subject: &str,
+    target: i64,
+    studied: i64,
+    recent: i64,
+    today: NaiveDate,
+    exam_date: Option<NaiveDate>,
+) -> SubjectPlan {
+    let remaining = (target - studied).max(0);
+    let days_left = exam_date.map(|d| (d - today).num_days().max(0));
+    let required_daily = match days_left {
+        Some(days) if days > 0 => (remaining + days - 1) / days,
+        Some(_) => remaining,
+        None => 0,
+    };
+    let recent_daily = recent / RATE_WINDOW_DAYS;
+
+    let projected = if remaining == 0 {
+        Some(today)
+    } else if recent_daily > 0 {
+        Some(today + Duration::days((remaining + recent_daily - 1) / recent_daily))
+    } else {
+        None
+    };
+
+    let on_track = match (projected, exam_date) {
+        (Some(p), Some(exam)) => p <= exam,
+        (None, Some(_)) => false,
+        _ => true,
+    };
+
+    let warning = if on_track {
+        None
+    } else {
+        Some(match projected {
+            Some(p) => format!(
+                "{}：按最近速度预计 {} 完成，晚于考试日期，需要每天学习 {} 分钟",
+                subject,
+                p.format("%Y-%m-%d"),
+                required_daily / 60
+            ),
+            None => format!("{}：最近 {} 天没有学习记录，需要每天学习 {} 分钟", subject, RATE_WINDOW_DAYS, required_daily / 60),
+        })
+    };
+
+    SubjectPlan {
+        subject: subject.to_string(),
+        exam_date: exam_date.map(|d| d.format("%Y-%m-%d").to_string()),
+        target,
+        studied,
+        remaining,
+        days_left,
+        required_daily,
+        recent_daily,
+        projected_completion: projected.map(|d| d.format("%Y-%m-%d").to_string()),
+        on_track,
+        warning,
+    }
+}
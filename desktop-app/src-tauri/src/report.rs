@@ -0,0 +1,273 @@
+use crate::Report;
+use chrono::NaiveDate;
+
+// 定期报告保存目录，与数据库文件在同一目录
+pub const REPORT_DIR: &str = "reports";
+
+pub fn is_valid_period(period: &str) -> bool {
+    matches!(period, "weekly" | "monthly")
+}
+
+pub fn is_valid_format(format: &str) -> bool {
+    matches!(format, "html" | "markdown")
+}
+
+pub fn extension(format: &str) -> &'static str {
+    if format == "html" {
+        "html"
+    } else {
+        "md"
+    }
+}
+
+pub fn file_name(report: &Report, format: &str) -> String {
+    format!("study-report-{}-{}.{}", report.period, report.start_date, extension(format))
+}
+
+pub fn render(report: &Report, format: &str) -> Result<String, String> {
+    match format {
+        "html" => Ok(html(report)),
+        "markdown" => Ok(markdown(report)),
+        _ => Err("无效的报告格式".to_string()),
+    }
+}
+
+fn title(report: &Report) -> String {
+    let kind = if report.period == "monthly" { "月报" } else { "周报" };
+    format!("学习{}（{} ~ {}）", kind, report.start_date, report.end_date)
+}
+
+fn previous_label(report: &Report) -> &'static str {
+    if report.period == "monthly" {
+        "上月"
+    } else {
+        "上周"
+    }
+}
+
+// 1 小时 25 分钟 / 40 分钟
+fn duration(seconds: i64) -> String {
+    let minutes = seconds / 60;
+    if minutes >= 60 {
+        format!("{} 小时 {} 分钟", minutes / 60, minutes % 60)
+    } else {
+        format!("{} 分钟", minutes)
+    }
+}
+
+// 与上一周期相比的变化，如 "+25%"；上一周期没有记录时只显示差值
+fn change(current: i64, previous: i64) -> String {
+    let diff = current - previous;
+    let sign = if diff >= 0 { "+" } else { "-" };
+    if previous > 0 {
+        format!("{}{:.0}%", sign, (diff.abs() as f64 / previous as f64) * 100.0)
+    } else {
+        format!("{}{}", sign, duration(diff.abs()))
+    }
+}
+
+fn average(report: &Report) -> i64 {
+    if report.days > 0 {
+        report.total / report.days
+    } else {
+        0
+    }
+}
+
+fn exam_line(name: &str, subject: &str, date: &str, days_left: i64) -> String {
+    let date = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map(|d| d.format("%m月%d日").to_string())
+        .unwrap_or_else(|_| date.to_string());
+    format!("{}（{}）：{}，还有 {} 天", subject, name, date, days_left)
+}
+
+fn summary_rows(report: &Report) -> Vec<(String, String)> {
+    vec![
+        ("总学习时长".to_string(), duration(report.total)),
+        (
+            format!("与{}相比", previous_label(report)),
+            format!("{}（{}：{}）", change(report.total, report.previous_total), previous_label(report), duration(report.previous_total)),
+        ),
+        ("日均学习时长".to_string(), duration(average(report))),
+        ("学习天数".to_string(), format!("{} / {} 天", report.studied_days, report.days)),
+        ("达标天数".to_string(), format!("{} / {} 天", report.goal_met_days, report.days)),
+        (
+            "连续达标".to_string(),
+            format!("{} 天（最长 {} 天）", report.streaks.current_streak, report.streaks.longest_streak),
+        ),
+    ]
+}
+
+pub fn markdown(report: &Report) -> String {
+    let mut out = format!("# {}\n\n", title(report));
+
+    out.push_str("## 概览\n\n| 项目 | 数值 |\n| --- | --- |\n");
+    for (label, value) in summary_rows(report) {
+        out.push_str(&format!("| {} | {} |\n", label, value));
+    }
+
+    if !report.subjects.is_empty() {
+        out.push_str(&format!("\n## 科目\n\n| 科目 | 时长 | {} | 变化 |\n| --- | --- | --- | --- |\n", previous_label(report)));
+        for s in &report.subjects {
+            out.push_str(&format!(
+                "| {} | {} | {} | {} |\n",
+                md_cell(&s.name),
+                duration(s.duration),
+                duration(s.previous_duration),
+                change(s.duration, s.previous_duration)
+            ));
+        }
+    }
+
+    if !report.course_stats.is_empty() {
+        out.push_str("\n## 课程\n\n| 课程 | 科目 | 时长 | 占比 | 章节完成度 |\n| --- | --- | --- | --- | --- |\n");
+        for c in &report.course_stats {
+            out.push_str(&format!(
+                "| {} | {} | {} | {:.1}% | {:.0}% |\n",
+                md_cell(&c.course_name),
+                md_cell(&c.subject),
+                duration(c.duration),
+                c.percent,
+                c.completion
+            ));
+        }
+    }
+
+    if !report.goal_progress.is_empty() {
+        out.push_str("\n## 目标\n\n");
+        for p in &report.goal_progress {
+            let mark = if p.met { "✓" } else { "✗" };
+            out.push_str(&format!("- {} {}：{} / {}\n", mark, p.label, duration(p.studied), duration(p.goal)));
+        }
+    }
+
+    if !report.exams.is_empty() {
+        out.push_str("\n## 考试倒计时\n\n");
+        for e in &report.exams {
+            out.push_str(&format!("- {}\n", exam_line(&e.exam_name, &e.subject, &e.date, e.days_left)));
+        }
+    }
+    out
+}
+
+fn md_cell(text: &str) -> String {
+    text.replace('|', "\\|")
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// 样式内联，单个文件即可打印或分享
+const STYLE: &str = "body{font-family:-apple-system,'PingFang SC','Microsoft YaHei',sans-serif;max-width:760px;margin:32px auto;padding:0 16px;color:#222}\
+h1{font-size:22px}h2{font-size:16px;margin-top:28px;border-bottom:1px solid #ddd;padding-bottom:4px}\
+table{width:100%;border-collapse:collapse;font-size:14px}th,td{text-align:left;padding:6px 8px;border-bottom:1px solid #eee}\
+th{color:#666;font-weight:normal}.bar{display:inline-block;height:8px;border-radius:4px;margin-right:6px;vertical-align:middle}\
+.met{color:#2e7d32}.missed{color:#c62828}ul{padding-left:20px;font-size:14px}\
+@media print{body{margin:0}}";
+
+fn html_table(headers: &[&str], rows: Vec<Vec<String>>) -> String {
+    let mut out = String::from("<table><tr>");
+    for h in headers {
+        out.push_str(&format!("<th>{}</th>", escape(h)));
+    }
+    out.push_str("</tr>");
+    for row in rows {
+        out.push_str("<tr>");
+        for cell in row {
+            out.push_str(&format!("<td>{}</td>", cell));
+        }
+        out.push_str("</tr>");
+    }
+    out.push_str("</table>");
+    out
+}
+
+pub fn html(report: &Report) -> String {
+    let title = escape(&title(report));
+    let mut body = format!("<h1>{}</h1>", title);
+
+    body.push_str("<h2>概览</h2>");
+    body.push_str(&html_table(
+        &["项目", "数值"],
+        summary_rows(report).into_iter().map(|(l, v)| vec![escape(&l), escape(&v)]).collect(),
+    ));
+
+    if !report.subjects.is_empty() {
+        let max = report.subjects.iter().map(|s| s.duration).max().unwrap_or(0).max(1);
+        body.push_str("<h2>科目</h2>");
+        body.push_str(&html_table(
+            &["科目", "时长", previous_label(report), "变化"],
+            report
+                .subjects
+                .iter()
+                .map(|s| {
+                    let width = s.duration * 120 / max;
+                    vec![
+                        format!(
+                            "<span class=\"bar\" style=\"width:{}px;background:{}\"></span>{}",
+                            width.max(2),
+                            escape(&s.color),
+                            escape(&s.name)
+                        ),
+                        duration(s.duration),
+                        duration(s.previous_duration),
+                        change(s.duration, s.previous_duration),
+                    ]
+                })
+                .collect(),
+        ));
+    }
+
+    if !report.course_stats.is_empty() {
+        body.push_str("<h2>课程</h2>");
+        body.push_str(&html_table(
+            &["课程", "科目", "时长", "占比", "章节完成度"],
+            report
+                .course_stats
+                .iter()
+                .map(|c| {
+                    vec![
+                        escape(&c.course_name),
+                        escape(&c.subject),
+                        duration(c.duration),
+                        format!("{:.1}%", c.percent),
+                        format!("{:.0}%", c.completion),
+                    ]
+                })
+                .collect(),
+        ));
+    }
+
+    if !report.goal_progress.is_empty() {
+        body.push_str("<h2>目标</h2><ul>");
+        for p in &report.goal_progress {
+            let (class, mark) = if p.met { ("met", "✓") } else { ("missed", "✗") };
+            body.push_str(&format!(
+                "<li><span class=\"{}\">{}</span> {}：{} / {}</li>",
+                class,
+                mark,
+                escape(&p.label),
+                duration(p.studied),
+                duration(p.goal)
+            ));
+        }
+        body.push_str("</ul>");
+    }
+
+    if !report.exams.is_empty() {
+        body.push_str("<h2>考试倒计时</h2><ul>");
+        for e in &report.exams {
+            body.push_str(&format!("<li>{}</li>", escape(&exam_line(&e.exam_name, &e.subject, &e.date, e.days_left))));
+        }
+        body.push_str("</ul>");
+    }
+
+    format!(
+        "<!DOCTYPE html>\n<html lang=\"zh-CN\">\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>{}</style>\n</head>\n<body>\n{}\n</body>\n</html>\n",
+        title, STYLE, body
+    )
+}
@@ -0,0 +1,77 @@
+use crate::{AdherenceItem, PlannedBlock, StudySession};
+use chrono::{Datelike, Duration, Local, NaiveDate, NaiveTime, TimeZone};
+
+// 实际学习占计划时长的比例达到多少算完成
+const HIT_RATIO: f64 = 0.8;
+
+pub fn parse_time(value: &str) -> Option<NaiveTime> {
+    NaiveTime::parse_from_str(value, "%H:%M").ok()
+}
+
+fn local_timestamp(date: NaiveDate, time: NaiveTime) -> Option<i64> {
+    Local.from_local_datetime(&date.and_time(time)).earliest().map(|t| t.timestamp())
+}
+
+// 计划块在某天是否生效：每周重复的按星期匹配，单次的按日期匹配
+pub fn occurs_on(block: &PlannedBlock, date: NaiveDate) -> bool {
+    match (&block.date, block.weekday) {
+        (Some(d), _) => *d == date.format("%Y-%m-%d").to_string(),
+        (None, Some(weekday)) => date.weekday().num_days_from_monday() == weekday,
+        _ => false,
+    }
+}
+
+// 计划块在某天的起止时间戳
+pub fn occurrence_range(block: &PlannedBlock, date: NaiveDate) -> Option<(i64, i64)> {
+    let start = local_timestamp(date, parse_time(&block.start_time)?)?;
+    let end = local_timestamp(date, parse_time(&block.end_time)?)?;
+    (end > start).then_some((start, end))
+}
+
+// 展开 start..=end 内的每次计划，与同一课程的实际会话比较
+pub fn adherence(
+    blocks: &[PlannedBlock],
+    sessions: &[StudySession],
+    course_name: impl Fn(&str) -> String,
+    start: NaiveDate,
+    end: NaiveDate,
+    now: i64,
+) -> Vec<AdherenceItem> {
+    let mut items = Vec::new();
+    let mut date = start;
+    while date <= end {
+        for block in blocks.iter().filter(|b| occurs_on(b, date)) {
+            let Some((planned_start, planned_end)) = occurrence_range(block, date) else {
+                continue;
+            };
+            let actual: i64 = sessions
+                .iter()
+                .filter(|s| s.course_id == block.course_id)
+                .map(|s| (s.end_time.min(planned_end) - s.start_time.max(planned_start)).max(0))
+                .sum();
+            let planned = planned_end - planned_start;
+            let status = if actual as f64 >= planned as f64 * HIT_RATIO {
+                "hit"
+            } else if planned_end > now {
+                "upcoming"
+            } else if actual > 0 {
+                "partial"
+            } else {
+                "missed"
+            };
+            items.push(AdherenceItem {
+                block_id: block.id.clone(),
+                course_id: block.course_id.clone(),
+                course_name: course_name(&block.course_id),
+                date: date.format("%Y-%m-%d").to_string(),
+                start_time: block.start_time.clone(),
+                end_time: block.end_time.clone(),
+                planned,
+                actual,
+                status: status.to_string(),
+            });
+        }
+        date += Duration::days(1);
+    }
+    items
+}
@@ -0,0 +1,53 @@
+// trigram 分词的 FTS5 只能匹配不少于 3 个字符的词，更短的词改用 LIKE
+const MIN_MATCH_CHARS: usize = 3;
+
+// 摘要在命中位置前后保留的字符数
+const SNIPPET_CONTEXT: usize = 20;
+
+pub fn terms(query: &str) -> Vec<String> {
+    query.split_whitespace().map(|t| t.to_string()).collect()
+}
+
+// 所有词都足够长时返回 FTS5 查询表达式，各词作为短语按 AND 组合
+pub fn match_expression(terms: &[String]) -> Option<String> {
+    if terms.is_empty() || terms.iter().any(|t| t.chars().count() < MIN_MATCH_CHARS) {
+        return None;
+    }
+    Some(
+        terms
+            .iter()
+            .map(|t| format!("\"{}\"", t.replace('"', "\"\"")))
+            .collect::<Vec<_>>()
+            .join(" "),
+    )
+}
+
+// 截取第一个命中词附近的文字，多行内容（如页面标题）取命中的那一行
+pub fn snippet(text: &str, terms: &[String]) -> String {
+    let lower = text.to_lowercase();
+    let line = lower
+        .lines()
+        .zip(text.lines())
+        .find(|(l, _)| terms.iter().any(|t| l.contains(&t.to_lowercase())))
+        .map(|(_, original)| original)
+        .unwrap_or_else(|| text.lines().next().unwrap_or(""));
+
+    let chars: Vec<char> = line.chars().collect();
+    let line_lower = line.to_lowercase();
+    let hit = terms
+        .iter()
+        .filter_map(|t| line_lower.find(&t.to_lowercase()))
+        .min()
+        .map(|byte| line_lower[..byte].chars().count())
+        .unwrap_or(0);
+    let start = hit.saturating_sub(SNIPPET_CONTEXT);
+    let end = (hit + SNIPPET_CONTEXT * 2).min(chars.len());
+    let mut result: String = chars[start.min(end)..end].iter().collect();
+    if start > 0 {
+        result.insert(0, '…');
+    }
+    if end < chars.len() {
+        result.push('…');
+    }
+    result
+}
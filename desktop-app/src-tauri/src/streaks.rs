@@ -0,0 +1,73 @@
+use crate::{DailyStat, Streaks};
+use chrono::{Datelike, Duration, NaiveDate};
+use std::collections::HashMap;
+
+// 休息日设置，逗号分隔的星期序号（0 = 周一 … 6 = 周日）
+pub fn parse_rest_days(value: &str) -> Vec<u32> {
+    value
+        .split(',')
+        .filter_map(|v| v.trim().parse().ok())
+        .filter(|d| *d < 7)
+        .collect()
+}
+
+// 连续达标天数：休息日未达标不算中断，今天还没达标也不算中断
+pub fn compute_streaks(daily: &[DailyStat], rest_days: &[u32], today: NaiveDate, today_goal: i64) -> Streaks {
+    let by_date: HashMap<NaiveDate, &DailyStat> = daily
+        .iter()
+        .filter_map(|d| NaiveDate::parse_from_str(&d.date, "%Y-%m-%d").ok().map(|date| (date, d)))
+        .collect();
+    let is_rest = |date: NaiveDate| rest_days.contains(&date.weekday().num_days_from_monday());
+    let goal_met = |date: NaiveDate| by_date.get(&date).map(|d| d.goal_met).unwrap_or(false);
+    let studied = |date: NaiveDate| by_date.get(&date).map(|d| d.duration > 0).unwrap_or(false);
+
+    let earliest = by_date.keys().min().copied().unwrap_or(today).min(today);
+
+    let mut current_streak = 0;
+    let mut date = today;
+    while date >= earliest {
+        if goal_met(date) {
+            current_streak += 1;
+        } else if date != today && !is_rest(date) {
+            break;
+        }
+        date -= Duration::days(1);
+    }
+
+    let mut longest_streak = 0;
+    let mut run = 0;
+    let mut date = earliest;
+    while date <= today {
+        if goal_met(date) {
+            run += 1;
+            longest_streak = longest_streak.max(run);
+        } else if date != today && !is_rest(date) {
+            run = 0;
+        }
+        date += Duration::days(1);
+    }
+
+    let days_studied_in = |days: i64| (0..days).filter(|i| studied(today - Duration::days(*i))).count() as i64;
+
+    let studied_days: Vec<&&DailyStat> = by_date.values().filter(|d| d.duration > 0).collect();
+    let average_per_studied_day = if studied_days.is_empty() {
+        0
+    } else {
+        studied_days.iter().map(|d| d.duration).sum::<i64>() / studied_days.len() as i64
+    };
+
+    let today_studied = by_date.get(&today).map(|d| d.duration).unwrap_or(0);
+    let today_goal_met = goal_met(today);
+
+    Streaks {
+        current_streak,
+        longest_streak,
+        days_studied_7: days_studied_in(7),
+        days_studied_30: days_studied_in(30),
+        average_per_studied_day,
+        today_studied,
+        today_goal,
+        today_goal_met,
+        at_risk: current_streak > 0 && !today_goal_met && !is_rest(today),
+    }
+}
@@ -0,0 +1,369 @@
+use crate::backend;
+use crate::crypto;
+use crate::db::Database;
+use crate::{SharedState, SyncPreview, SyncStatus};
+use parking_lot::{Condvar, Mutex};
+use std::time::Duration;
+
+// 失败后的重试间隔：30 秒起每次翻倍，最长 1 小时
+const RETRY_BASE_SECS: i64 = 30;
+const RETRY_MAX_SECS: i64 = 3600;
+// 没有到期任务时也定期醒来，以便读取新的设置
+const IDLE_CHECK_SECS: i64 = 60;
+
+// 跨设备同步的设置项，其余设置（同步地址、上次同步时间等）只属于本机
+pub const SYNCED_SETTINGS: [&str; 3] = ["daily_goal", "exam_date", "rest_days"];
+
+struct Engine {
+    requested: bool,
+    running: bool,
+    failures: u32,
+    next_retry: Option<i64>,
+    last_attempt: Option<i64>,
+}
+
+static ENGINE: Mutex<Engine> = Mutex::new(Engine {
+    requested: false,
+    running: false,
+    failures: 0,
+    next_retry: None,
+    last_attempt: None,
+});
+static WAKE: Condvar = Condvar::new();
+// 同一时间只进行一次同步（后台线程和手动同步共用）
+static RUNNING: Mutex<()> = Mutex::new(());
+// 拉取时已预览的远端数据，确认后写入
+static PULLED: Mutex<Option<serde_json::Value>> = Mutex::new(None);
+
+struct Config {
+    configured: bool,
+    enabled: bool,
+    interval: i64,
+}
+
+fn config(db: &Database) -> Config {
+    Config {
+        configured: backend::from_settings(db).is_ok(),
+        enabled: db.get_setting("auto_sync_enabled").map(|v| v == "true").unwrap_or(false),
+        interval: db.get_setting("auto_sync_interval").and_then(|v| v.parse().ok()).unwrap_or(300).max(10),
+    }
+}
+
+pub fn backoff(failures: u32) -> i64 {
+    if failures == 0 {
+        return 0;
+    }
+    RETRY_BASE_SECS
+        .saturating_mul(1i64 << (failures - 1).min(16))
+        .min(RETRY_MAX_SECS)
+}
+
+// 请求尽快同步（如学习暂停时），由后台线程执行
+pub fn request() {
+    ENGINE.lock().requested = true;
+    WAKE.notify_all();
+}
+
+// 设置变更后唤醒后台线程重新计算下次同步时间
+pub fn wake() {
+    WAKE.notify_all();
+}
+
+// 完整上传的数据（第 1 版协议），与原先前端提交的格式一致
+pub fn payload(db: &Database) -> serde_json::Value {
+    serde_json::json!({
+        "courses": db.get_courses(),
+        "studyLogs": db.get_all_study_logs(),
+        "sessions": db.get_sessions("0000-01-01", "9999-12-31"),
+        "settings": {
+            "daily_goal": db.get_daily_goal(),
+            "exam_date": db.get_exam_date().unwrap_or_default()
+        }
+    })
+}
+
+pub fn cursor(db: &Database, key: &str) -> i64 {
+    db.get_setting(key).and_then(|v| v.parse().ok()).unwrap_or(0)
+}
+
+// 更换同步方式、服务器或用户后需要重新完整同步
+pub fn reset_cursors(db: &Database) {
+    db.set_setting("sync_cursor", "0");
+    db.set_setting("sync_server_cursor", "0");
+    db.set_setting("sync_pulled_at", "0");
+    db.set_setting("sync_seen_files", "");
+    db.set_setting("sync_file_hash", "");
+}
+
+// 判断冲突的起点：上次同步或拉取之后本地修改过的记录才可能与远端冲突
+// 拉取写入的记录带着远端的修改时间，不应被当作本地修改
+pub fn conflict_since(db: &Database) -> i64 {
+    cursor(db, "sync_cursor").max(cursor(db, "sync_pulled_at"))
+}
+
+// 端到端加密：数据密钥、更换密钥后尚未上传时的旧密钥，以及用同步密码加密的数据密钥
+pub struct Keys {
+    pub key: crypto::Key,
+    pub previous: Option<crypto::Key>,
+    pub wrapped: serde_json::Value,
+}
+
+impl Keys {
+    fn known(&self) -> Vec<crypto::Key> {
+        std::iter::once(self.key).chain(self.previous).collect()
+    }
+
+    // 远端数据是否由本机已知的密钥加密
+    pub fn knows(&self, key_id: &serde_json::Value) -> bool {
+        self.known().iter().any(|k| *key_id == crypto::key_id(k))
+    }
+}
+
+pub fn keys(db: &Database) -> Option<Keys> {
+    let key = db.get_setting("sync_key").and_then(|k| crypto::decode_key(&k))?;
+    Some(Keys {
+        key,
+        previous: db.get_setting("sync_previous_key").and_then(|k| crypto::decode_key(&k)),
+        wrapped: db
+            .get_setting("sync_wrapped_key")
+            .and_then(|w| serde_json::from_str(&w).ok())
+            .unwrap_or_default(),
+    })
+}
+
+fn store_keys(db: &Database, key: &crypto::Key, wrapped: &serde_json::Value) {
+    db.set_setting("sync_key", &crypto::encode_key(key));
+    db.set_setting("sync_wrapped_key", &wrapped.to_string());
+}
+
+// 是否启用加密同步，以及当前数据密钥的标识
+pub fn encryption(db: &Database) -> (bool, String) {
+    match keys(db) {
+        Some(keys) => (true, crypto::key_id(&keys.key)),
+        None => (false, String::new()),
+    }
+}
+
+// 启用加密同步：远端已有加密数据时用同步密码解出数据密钥，否则生成新的数据密钥
+pub fn enable_encryption(state: &SharedState, passphrase: &str) -> Result<(), String> {
+    crypto::check_passphrase(passphrase)?;
+    let backend = backend::from_settings(&state.lock().db)?;
+    let (key, wrapped) = match backend.wrapped_key()? {
+        Some(wrapped) => (crypto::unwrap_key(&wrapped, passphrase)?, wrapped),
+        None => {
+            let key = crypto::new_key();
+            (key, crypto::wrap_key(&key, passphrase)?)
+        }
+    };
+    let s = state.lock();
+    store_keys(&s.db, &key, &wrapped);
+    s.db.set_setting("sync_previous_key", "");
+    drop(s);
+    request();
+    Ok(())
+}
+
+// 修改同步密码；rotate 时同时更换数据密钥，下次同步用新密钥重新加密全部数据，
+// 其它设备需要输入新密码才能继续同步
+pub fn change_passphrase(state: &SharedState, passphrase: &str, rotate: bool) -> Result<(), String> {
+    crypto::check_passphrase(passphrase)?;
+    let current = keys(&state.lock().db).ok_or("未启用加密同步")?;
+    let key = if rotate { crypto::new_key() } else { current.key };
+    let wrapped = crypto::wrap_key(&key, passphrase)?;
+    let s = state.lock();
+    // 服务器上的数据仍用旧密钥加密，上传前需要旧密钥解密
+    if rotate && current.previous.is_none() {
+        s.db.set_setting("sync_previous_key", &crypto::encode_key(&current.key));
+    }
+    store_keys(&s.db, &key, &wrapped);
+    drop(s);
+    request();
+    Ok(())
+}
+
+// 用恢复短语找回数据密钥并设置新的同步密码，其它设备同步后也改用新密码
+pub fn recover_key(state: &SharedState, phrase: &str, passphrase: &str) -> Result<(), String> {
+    crypto::check_passphrase(passphrase)?;
+    let key = crypto::key_from_phrase(phrase)?;
+    let wrapped = crypto::wrap_key(&key, passphrase)?;
+    let s = state.lock();
+    store_keys(&s.db, &key, &wrapped);
+    s.db.set_setting("sync_previous_key", "");
+    drop(s);
+    request();
+    Ok(())
+}
+
+pub fn recovery_phrase(db: &Database) -> Result<String, String> {
+    keys(db)
+        .map(|keys| crypto::recovery_phrase(&keys.key))
+        .ok_or_else(|| "未启用加密同步".to_string())
+}
+
+// 关闭加密后改回明文增量同步，需要重新上传全部数据；先删除远端的加密数据，
+// 否则服务器会一直拒绝明文同步。未配置同步时只清除本机的密钥
+pub fn disable_encryption(state: &SharedState) -> Result<(), String> {
+    if let Ok(backend) = backend::from_settings(&state.lock().db) {
+        backend.remove_encrypted()?;
+    }
+    let s = state.lock();
+    for key in ["sync_key", "sync_previous_key", "sync_wrapped_key"] {
+        s.db.set_setting(key, "");
+    }
+    reset_cursors(&s.db);
+    Ok(())
+}
+
+pub fn decrypt(keys: &Keys, aad: &str, envelope: &serde_json::Value) -> Result<serde_json::Value, String> {
+    crypto::decrypt(&keys.known(), aad, envelope)
+}
+
+// 其它设备修改了同步密码（数据密钥不变）时采用较新的密码
+pub fn adopt_wrapped_key(db: &Database, keys: &mut Keys, remote: &serde_json::Value) {
+    if remote["key_id"] == crypto::key_id(&keys.key) && remote["created_at"].as_i64() > keys.wrapped["created_at"].as_i64() {
+        keys.wrapped = remote.clone();
+        db.set_setting("sync_wrapped_key", &keys.wrapped.to_string());
+    }
+}
+
+pub fn changed(result: &SyncPreview) -> usize {
+    result.new_courses.len() + result.updated_courses.len() + result.study_logs + result.sessions + result.settings.len() + result.deleted
+}
+
+// 执行一次同步并记录结果；网络请求期间不持有状态锁
+// 加锁顺序固定为先状态锁后引擎锁
+pub fn run_once(state: &SharedState) -> Result<String, String> {
+    let _running = RUNNING.lock();
+    let now = chrono::Utc::now().timestamp();
+    let backend = backend::from_settings(&state.lock().db)?;
+    {
+        let mut engine = ENGINE.lock();
+        engine.running = true;
+        engine.requested = false;
+        engine.last_attempt = Some(now);
+    }
+
+    let result = backend.exchange(state);
+
+    let finished = chrono::Utc::now().timestamp();
+    {
+        let s = state.lock();
+        match &result {
+            Ok(_) => s.db.set_setting("sync_last_success", &finished.to_string()),
+            Err(e) => {
+                s.db.set_setting("sync_last_failure", &finished.to_string());
+                s.db.set_setting("sync_last_error", e);
+            }
+        }
+    }
+    // 状态锁之后再取引擎锁，与其它地方的加锁顺序一致
+    let mut engine = ENGINE.lock();
+    engine.running = false;
+    if result.is_ok() {
+        engine.failures = 0;
+        engine.next_retry = None;
+    } else {
+        engine.failures += 1;
+        engine.next_retry = Some(finished + backoff(engine.failures));
+    }
+    result
+}
+
+// 拉取：获取远端的完整数据，预览与本地合并后的变化；尚未上传的本地修改与远端不同时记为冲突
+pub fn preview_pull(state: &SharedState) -> Result<SyncPreview, String> {
+    let (backend, keys) = {
+        let s = state.lock();
+        (backend::from_settings(&s.db)?, keys(&s.db))
+    };
+    let remote = backend.fetch(keys.as_ref())?;
+    let s = state.lock();
+    let preview = s.db.preview_sync_changes(&remote, &SYNCED_SETTINGS, conflict_since(&s.db))?;
+    *PULLED.lock() = Some(remote);
+    Ok(preview)
+}
+
+// 写入预览过的数据；backup 在写入前执行，失败时不写入
+pub fn apply_pull(db: &Database, backup: impl FnOnce() -> Result<(), String>) -> Result<SyncPreview, String> {
+    let mut pulled = PULLED.lock();
+    let remote = pulled.as_ref().ok_or("请先获取远端数据")?;
+    backup()?;
+    let pulled_at = chrono::Utc::now().timestamp_millis();
+    let result = db.apply_sync_changes(remote, &SYNCED_SETTINGS, conflict_since(db))?;
+    db.set_setting("sync_pulled_at", &pulled_at.to_string());
+    *pulled = None;
+    Ok(result)
+}
+
+pub fn status(db: &Database) -> SyncStatus {
+    let config = config(db);
+    let engine = ENGINE.lock();
+    let last_success: Option<i64> = db.get_setting("sync_last_success").and_then(|v| v.parse().ok());
+    let last_failure: Option<i64> = db.get_setting("sync_last_failure").and_then(|v| v.parse().ok());
+    let configured = config.configured;
+    SyncStatus {
+        configured,
+        enabled: config.enabled,
+        interval: config.interval,
+        running: engine.running,
+        last_success,
+        last_failure,
+        // 最近一次同步成功后不再显示旧的错误
+        last_error: match (last_success, last_failure) {
+            (Some(ok), Some(failed)) if ok >= failed => None,
+            _ => db.get_setting("sync_last_error").filter(|e| !e.is_empty()),
+        },
+        failures: engine.failures,
+        next_sync: if configured { next_due(&engine, &config, last_success) } else { None },
+        conflicts: db.count_sync_conflicts(),
+    }
+}
+
+// 下次同步时间：失败后只按退避时间重试，否则按间隔定时同步；未开启自动同步时都不安排
+fn next_due(engine: &Engine, config: &Config, last_success: Option<i64>) -> Option<i64> {
+    if engine.requested {
+        return Some(chrono::Utc::now().timestamp());
+    }
+    if !config.enabled {
+        return None;
+    }
+    if engine.failures > 0 {
+        return engine.next_retry;
+    }
+    Some(engine.last_attempt.or(last_success).unwrap_or(0) + config.interval)
+}
+
+// 后台同步线程：按 auto_sync_interval 定时同步，暂停时的同步请求立即执行，失败后退避重试
+pub fn start_engine(state: SharedState) {
+    loop {
+        let (due, wait) = {
+            let s = state.lock();
+            let config = config(&s.db);
+            let last_success = s.db.get_setting("sync_last_success").and_then(|v| v.parse().ok());
+            drop(s);
+            let configured = config.configured;
+            let mut engine = ENGINE.lock();
+            if !configured {
+                engine.requested = false;
+            }
+            let now = chrono::Utc::now().timestamp();
+            match next_due(&engine, &config, last_success).filter(|_| configured) {
+                Some(at) if at <= now => (true, 0),
+                Some(at) => (false, (at - now).min(IDLE_CHECK_SECS)),
+                None => (false, IDLE_CHECK_SECS),
+            }
+        };
+
+        if due {
+            match run_once(&state) {
+                Ok(_) => println!("Auto sync completed"),
+                Err(e) => println!("Auto sync failed: {}", e),
+            }
+            continue;
+        }
+
+        let mut engine = ENGINE.lock();
+        if !engine.requested {
+            WAKE.wait_for(&mut engine, Duration::from_secs(wait as u64));
+        }
+    }
+}
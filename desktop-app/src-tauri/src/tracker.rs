@@ -0,0 +1,217 @@
+use crate::notify::send_notification;
+use crate::{goals, sync, AppState, Chapter, Course, SessionNote, SharedState};
+
+// 每段会话最多保存的页面标题数
+const MAX_TITLES: usize = 20;
+
+// 会话追踪：浏览器扩展（/status）和桌面应用监听都通过这里上报
+// context 为页面 URL 或窗口标题，用于匹配章节；切换章节时会话按章节分段记录
+// title 为页面或窗口标题，随会话保存用于搜索
+pub fn report_status(state: &SharedState, course_id: &str, active: bool, timestamp: i64, context: &str, title: &str) {
+    let mut s = state.lock();
+    let today = chrono::Local::now().format("%Y-%m-%d").to_string();
+
+    if active {
+        let switched_course = s.current_course_id.as_deref() != Some(course_id);
+        let is_new_session = s.session_start.is_none();
+        let chapters = s.db.get_chapters(course_id);
+        let chapter_id = match_chapter(&chapters, context).map(|c| c.id.clone());
+        let switched_chapter = !switched_course && s.current_chapter_id != chapter_id;
+
+        // 切换课程或章节时，保存之前的学习时长
+        if (switched_course || switched_chapter) && !is_new_session {
+            if let (Some(prev_id), Some(start)) = (s.current_course_id.clone(), s.segment_start) {
+                if timestamp > start {
+                    save_segment(&mut s, &prev_id, &today, start, timestamp);
+                }
+            }
+            s.segment_start = Some(timestamp);
+        }
+        s.current_chapter_id = chapter_id;
+
+        // 发送通知（仅在开始或切换时）
+        if is_new_session || switched_course {
+            if let Some(course) = s.db.get_course(course_id) {
+                if is_new_session {
+                    drop(s);
+                    send_notification("开始学习", &format!("正在学习：{}", course.name), state);
+                    s = state.lock();
+                } else {
+                    drop(s);
+                    send_notification("切换课程", &format!("正在学习：{}", course.name), state);
+                    s = state.lock();
+                }
+            }
+            // 只在开始或切换时重置 session_start，标签属于上一个会话
+            s.session_start = Some(timestamp);
+            s.segment_start = Some(timestamp);
+            s.current_tags.clear();
+            s.pending_note = None;
+            s.current_titles.clear();
+        }
+
+        let title = title.trim();
+        if !title.is_empty() && !s.current_titles.iter().any(|t| t == title) && s.current_titles.len() < MAX_TITLES {
+            s.current_titles.push(title.to_string());
+        }
+
+        s.current_course_id = Some(course_id.to_string());
+        s.last_report_time = Some(timestamp);
+
+    } else if let (Some(current_id), Some(start)) = (s.current_course_id.clone(), s.session_start) {
+        if current_id == course_id {
+            if timestamp > start {
+                let segment_start = s.segment_start.unwrap_or(start);
+                if timestamp > segment_start {
+                    save_segment(&mut s, &current_id, &today, segment_start, timestamp);
+                }
+
+                if let Some(course) = s.db.get_course(&current_id) {
+                    let total_today = s.db.get_studied_duration(&today);
+                    let mins = total_today / 60;
+                    let mut msg = format!("{}：今日已学习 {} 分钟", course.name, mins);
+                    // 附上与该课程相关的分项目标进度
+                    let now = chrono::Local::now().date_naive();
+                    for p in s.db.get_goal_progress(now) {
+                        let related = (p.scope == "course" && p.target == course.id)
+                            || (p.scope == "subject" && p.target == course.subject);
+                        if related {
+                            msg.push('\n');
+                            msg.push_str(&goals::format_progress(&p));
+                        }
+                    }
+                    drop(s);
+                    send_notification("学习暂停", &msg, state);
+                    s = state.lock();
+                }
+
+                // 检查是否启用暂停时同步
+                let sync_on_pause = s.db.get_setting("sync_on_pause")
+                    .map(|v| v == "true")
+                    .unwrap_or(false);
+                if sync_on_pause {
+                    sync::request();
+                }
+            }
+            s.current_course_id = None;
+            s.current_chapter_id = None;
+            s.session_start = None;
+            s.segment_start = None;
+            s.last_report_time = None;
+            s.current_tags.clear();
+            s.pending_note = None;
+            s.current_titles.clear();
+        }
+    }
+}
+
+// 记录一段会话，带上当前会话的章节、标签、页面标题和待写入的备注
+pub fn save_segment(s: &mut AppState, course_id: &str, date: &str, start: i64, end: i64) {
+    let titles = std::mem::take(&mut s.current_titles);
+    let Ok(id) = s.db.record_session(course_id, s.current_chapter_id.as_deref(), &s.current_tags, date, start, end) else {
+        return;
+    };
+    if !titles.is_empty() {
+        s.db.set_session_titles(&id, &titles);
+    }
+    if let Some(note) = s.pending_note.take() {
+        let _ = s.db.annotate_session(&id, &note.note, note.focus);
+    }
+}
+
+// 会话备注：指定 id 时直接写入；学习中先暂存，记录会话时写入；否则写到最近一次会话
+pub fn annotate_session(s: &mut AppState, session_id: Option<&str>, note: String, focus: Option<i64>) -> Result<(), String> {
+    if focus.is_some_and(|f| !(1..=5).contains(&f)) {
+        return Err("专注度应在 1 到 5 之间".to_string());
+    }
+    let target = match session_id {
+        Some(id) => id.to_string(),
+        None if s.session_start.is_some() => {
+            s.pending_note = Some(SessionNote { note: note.trim().to_string(), focus });
+            return Ok(());
+        }
+        None => s.db.get_last_session_id().ok_or("还没有学习记录")?,
+    };
+    s.db.annotate_session(&target, note.trim(), focus)
+}
+
+// 通配符匹配，与扩展中的 matchPattern 一致：* 匹配任意字符，不区分大小写，子串匹配
+pub fn matches_pattern(text: &str, pattern: &str) -> bool {
+    if pattern.is_empty() {
+        return false;
+    }
+    let text = text.to_lowercase();
+    let pattern = pattern.to_lowercase();
+    let mut rest = text.as_str();
+    for part in pattern.split('*').filter(|p| !p.is_empty()) {
+        match rest.find(part) {
+            Some(pos) => rest = &rest[pos + part.len()..],
+            None => return false,
+        }
+    }
+    true
+}
+
+// 桌面应用规则：应用名（WM_CLASS）和窗口标题，两者都填写时需同时匹配
+pub fn match_window<'a>(courses: &'a [Course], app_names: &[&str], title: &str) -> Option<&'a Course> {
+    courses.iter().find(|c| {
+        if c.app_pattern.is_empty() && c.title_pattern.is_empty() {
+            return false;
+        }
+        let app_ok = c.app_pattern.is_empty()
+            || app_names.iter().any(|name| matches_pattern(name, &c.app_pattern));
+        let title_ok = c.title_pattern.is_empty() || matches_pattern(title, &c.title_pattern);
+        app_ok && title_ok
+    })
+}
+
+// 章节规则按顺序匹配 URL 或窗口标题，未匹配时只记到课程
+pub fn match_chapter<'a>(chapters: &'a [Chapter], context: &str) -> Option<&'a Chapter> {
+    chapters.iter().find(|c| matches_pattern(context, &c.url_pattern))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn course(id: &str, app_pattern: &str, title_pattern: &str) -> Course {
+        Course {
+            id: id.to_string(),
+            name: id.to_string(),
+            subject: String::new(),
+            url_pattern: String::new(),
+            app_pattern: app_pattern.to_string(),
+            title_pattern: title_pattern.to_string(),
+            subject_id: String::new(),
+        }
+    }
+
+    #[test]
+    fn pattern_is_case_insensitive_substring_with_wildcards() {
+        assert!(matches_pattern("Anki - 高数", "anki"));
+        assert!(matches_pattern("Lecture 3 - Linear Algebra.pdf", "lecture*algebra"));
+        assert!(!matches_pattern("Algebra lecture", "lecture*algebra"));
+        assert!(!matches_pattern("anything", ""));
+    }
+
+    #[test]
+    fn window_matches_app_name_or_title_rules() {
+        let courses = vec![
+            course("none", "", ""),
+            course("anki", "anki", ""),
+            course("pdf", "evince", "*线性代数*"),
+            course("title", "", "leetcode"),
+        ];
+        let id = |names: &[&str], title: &str| match_window(&courses, names, title).map(|c| c.id.as_str());
+
+        // WM_CLASS 的 instance 或 class 任一匹配即可
+        assert_eq!(id(&["anki", "Anki"], "用户 1 - Anki"), Some("anki"));
+        assert_eq!(id(&["", "Anki"], ""), Some("anki"));
+        // 应用名和标题都填写时需同时匹配
+        assert_eq!(id(&["evince", "Evince"], "线性代数讲义.pdf"), Some("pdf"));
+        assert_eq!(id(&["evince", "Evince"], "概率论.pdf"), None);
+        assert_eq!(id(&["firefox", "Firefox"], "Problems - LeetCode"), Some("title"));
+        // 没有桌面规则的课程不参与匹配
+        assert_eq!(id(&["none"], "none"), None);
+    }
+}